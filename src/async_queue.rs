@@ -0,0 +1,157 @@
+//! An async wrapper around `HashQueue`, enabled by the `tokio` feature. Every operation runs on tokio's blocking thread pool with
+//! `tokio::task::spawn_blocking`, so sled's reads and flushes never block the async executor.
+
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::errors::HashQueueError;
+use crate::hash_queue::HashQueue;
+
+///Name: AsyncHashQueue
+///
+/// Desc: A `HashQueue` that can be used from async code. The queue is held behind an `Arc<Mutex<...>>`, so the wrapper is cheap to clone,
+/// and every clone uses the same queue. Operations run one at a time, in the order they take the lock.
+///
+/// Additional notes: If an operation panics, the panic is passed on to the task awaiting it. A queue whose operation panicked can't be used again.
+///
+/// Usage:
+///```
+/// use set_deque::async_queue::AsyncHashQueue;
+/// use set_deque::hash_queue::HashQueue;
+///
+/// let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+/// runtime.block_on(async {
+///     let hash_queue = AsyncHashQueue::from(HashQueue::open_temp("test").unwrap());
+///
+///     hash_queue.push_back(1).await.unwrap();
+///
+///     assert_eq!(Some(1), hash_queue.pop_front().await.unwrap());
+/// });
+/// ```
+pub struct AsyncHashQueue<T> {
+    queue: Arc<Mutex<HashQueue<T>>>,
+}
+
+impl<T> Clone for AsyncHashQueue<T> {
+    fn clone(&self) -> Self {
+        Self {
+            queue: self.queue.clone(),
+        }
+    }
+}
+
+impl<T> From<HashQueue<T>> for AsyncHashQueue<T> {
+    fn from(queue: HashQueue<T>) -> Self {
+        Self {
+            queue: Arc::new(Mutex::new(queue)),
+        }
+    }
+}
+
+impl<T> AsyncHashQueue<T>
+    where
+        T: Hash + Eq + Clone + Serialize + DeserializeOwned + Debug + Send + Sync + 'static,
+{
+    ///Name: open
+    ///
+    /// Desc: This function opens a HashQueue like `HashQueue::open`, on the blocking thread pool.
+    pub async fn open<P: AsRef<Path>, V: AsRef<[u8]>>(path: P, name: V) -> Result<Self, HashQueueError> {
+        let path = path.as_ref().to_path_buf();
+        let name = name.as_ref().to_vec();
+        let queue = Self::spawn(move || HashQueue::open(path, name)).await?;
+        Ok(queue.into())
+    }
+
+    ///Name: push_back
+    ///
+    /// Desc: See `HashQueue::push_back`.
+    pub async fn push_back(&self, value: T) -> Result<bool, HashQueueError> {
+        self.run(move |queue| queue.push_back(value)).await
+    }
+
+    ///Name: push_front
+    ///
+    /// Desc: See `HashQueue::push_front`.
+    pub async fn push_front(&self, value: T) -> Result<bool, HashQueueError> {
+        self.run(move |queue| queue.push_front(value)).await
+    }
+
+    ///Name: pop_front
+    ///
+    /// Desc: See `HashQueue::pop_front`.
+    pub async fn pop_front(&self) -> Result<Option<T>, HashQueueError> {
+        self.run(|queue| queue.pop_front()).await
+    }
+
+    ///Name: pop_back
+    ///
+    /// Desc: See `HashQueue::pop_back`.
+    pub async fn pop_back(&self) -> Result<Option<T>, HashQueueError> {
+        self.run(|queue| queue.pop_back()).await
+    }
+
+    ///Name: front
+    ///
+    /// Desc: See `HashQueue::front`.
+    pub async fn front(&self) -> Result<Option<T>, HashQueueError> {
+        self.run(|queue| queue.front()).await
+    }
+
+    ///Name: back
+    ///
+    /// Desc: See `HashQueue::back`.
+    pub async fn back(&self) -> Result<Option<T>, HashQueueError> {
+        self.run(|queue| queue.back()).await
+    }
+
+    ///Name: len
+    ///
+    /// Desc: See `HashQueue::len`.
+    pub async fn len(&self) -> Result<usize, HashQueueError> {
+        self.run(|queue| Ok(queue.len())).await
+    }
+
+    ///Name: is_empty
+    ///
+    /// Desc: See `HashQueue::is_empty`.
+    pub async fn is_empty(&self) -> Result<bool, HashQueueError> {
+        self.run(|queue| Ok(queue.is_empty())).await
+    }
+
+    ///Name: flush
+    ///
+    /// Desc: See `HashQueue::flush`.
+    pub async fn flush(&self) -> Result<(), HashQueueError> {
+        self.run(|queue| queue.flush()).await
+    }
+
+    ///Name: run
+    ///
+    /// Desc: This function locks the queue and calls `f` with it on the blocking thread pool, for operations this wrapper doesn't have an async version of.
+    pub async fn run<R, F>(&self, f: F) -> Result<R, HashQueueError>
+        where
+            R: Send + 'static,
+            F: FnOnce(&mut HashQueue<T>) -> Result<R, HashQueueError> + Send + 'static,
+    {
+        let queue = self.queue.clone();
+        Self::spawn(move || f(&mut queue.lock().expect("run: an earlier operation on the queue panicked"))).await
+    }
+
+    //This is an internal function that runs `f` on the blocking thread pool, and passes on any panic to the caller.
+    async fn spawn<R, F>(f: F) -> Result<R, HashQueueError>
+        where
+            R: Send + 'static,
+            F: FnOnce() -> Result<R, HashQueueError> + Send + 'static,
+    {
+        match tokio::task::spawn_blocking(f).await {
+            Ok(result) => result,
+            Err(error) if error.is_panic() => std::panic::resume_unwind(error.into_panic()),
+            Err(error) => Err(std::io::Error::from(error).into()),
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use std::fmt::Debug;
+#[cfg(feature = "compression")]
+use std::io::{Read, Write};
+
+#[cfg(feature = "compression")]
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::errors::HashQueueError;
+
+/// Name: Codec
+///
+/// Desc: Controls how a HashQueue turns its elements into the bytes stored in sled, and back again. The codec only applies to the elements themselves,
+/// the keys and any sidecar trees are stored the same way whichever codec is used.
+///
+/// Additional notes: A queue must always be opened with the codec it was written with. Opening it with a different codec will fail to decode the elements already stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Codec {
+    /// Stores elements with `bincode`. This is compact, and is what every queue used before codecs could be chosen.
+    #[default]
+    Bincode,
+    /// Stores elements as UTF-8 JSON with `serde_json`. This is larger than `Bincode`, but can be read by other tools and languages.
+    Json,
+}
+
+impl Codec {
+    /// Turns `value` into the bytes stored in the tree.
+    pub fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, HashQueueError> {
+        match self {
+            Codec::Bincode => Ok(bincode::serialize(value)?),
+            Codec::Json => Ok(serde_json::to_vec(value)?),
+        }
+    }
+
+    /// Turns bytes read from the tree back into an element.
+    pub fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, HashQueueError> {
+        match self {
+            Codec::Bincode => Ok(bincode::deserialize(bytes)?),
+            Codec::Json => Ok(serde_json::from_slice(bytes)?),
+        }
+    }
+}
+
+//These are internal functions that compress encoded elements before they are stored, and decompress them once they are read, see `HashQueueOptions::compress`.
+#[cfg(feature = "compression")]
+pub(crate) fn compress(bytes: &[u8]) -> Result<Vec<u8>, HashQueueError> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(bytes)?;
+    Ok(encoder.finish()?)
+}
+
+#[cfg(feature = "compression")]
+pub(crate) fn decompress(bytes: &[u8]) -> Result<Vec<u8>, HashQueueError> {
+    let mut decompressed = Vec::new();
+    DeflateDecoder::new(bytes).read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
+/// Name: ByteCodec
+///
+/// Desc: A reversible transformation applied to an element's bytes after the `Codec` has encoded it, and undone before the `Codec` decodes it.
+/// This is how compression and encryption are added to a queue, see `HashQueueOptions::byte_codec`. `decode` must return exactly the bytes `encode` was given.
+///
+/// Additional notes: The crate doesn't ship an encryption codec, so that callers can bring the cipher and key management they already use. A codec that fails,
+///                   for example because a value can't be decrypted, can return any `HashQueueError`, an `std::io::Error` converts into one with `?`.
+///
+/// Usage:
+///```
+/// use set_deque::codec::ByteCodec;
+/// use set_deque::errors::HashQueueError;
+///
+/// #[derive(Debug)]
+/// struct Reversed;
+///
+/// impl ByteCodec for Reversed {
+///     fn encode(&self, bytes: &[u8]) -> Result<Vec<u8>, HashQueueError> {
+///         Ok(bytes.iter().rev().copied().collect())
+///     }
+///
+///     fn decode(&self, bytes: &[u8]) -> Result<Vec<u8>, HashQueueError> {
+///         self.encode(bytes)
+///     }
+/// }
+///
+/// assert_eq!(vec![3, 2, 1], Reversed.encode(&[1, 2, 3]).unwrap());
+/// ```
+pub trait ByteCodec: Debug + Send + Sync {
+    /// Transforms the encoded element into the bytes stored in the tree.
+    fn encode(&self, bytes: &[u8]) -> Result<Vec<u8>, HashQueueError>;
+
+    /// Turns bytes read from the tree back into the encoded element.
+    fn decode(&self, bytes: &[u8]) -> Result<Vec<u8>, HashQueueError>;
+}
+
+/// Name: Deflate
+///
+/// Desc: A `ByteCodec` that compresses bytes with DEFLATE, the same compression `HashQueueOptions::compress` uses. This is intended for composing
+/// compression with another codec in a `CompositeCodec`, for example compressing elements before they are encrypted.
+#[cfg(feature = "compression")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Deflate;
+
+#[cfg(feature = "compression")]
+impl ByteCodec for Deflate {
+    fn encode(&self, bytes: &[u8]) -> Result<Vec<u8>, HashQueueError> {
+        compress(bytes)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<Vec<u8>, HashQueueError> {
+        decompress(bytes)
+    }
+}
+
+/// Name: CompositeCodec
+///
+/// Desc: A `ByteCodec` that applies `first` and then `second` when encoding, and undoes them in the opposite order when decoding.
+/// To compress elements and then encrypt them, `first` is the compression and `second` the encryption, since encrypted bytes don't compress.
+///
+/// Usage:
+///```
+/// use set_deque::codec::{ByteCodec, CompositeCodec};
+/// use set_deque::errors::HashQueueError;
+///
+/// #[derive(Debug)]
+/// struct Append(u8);
+///
+/// impl ByteCodec for Append {
+///     fn encode(&self, bytes: &[u8]) -> Result<Vec<u8>, HashQueueError> {
+///         Ok([bytes, &[self.0]].concat())
+///     }
+///
+///     fn decode(&self, bytes: &[u8]) -> Result<Vec<u8>, HashQueueError> {
+///         Ok(bytes[..bytes.len() - 1].to_vec())
+///     }
+/// }
+///
+/// let codec = CompositeCodec::new(Append(1), Append(2));
+///
+/// assert_eq!(vec![0, 1, 2], codec.encode(&[0]).unwrap());
+/// assert_eq!(vec![0], codec.decode(&[0, 1, 2]).unwrap());
+/// ```
+#[derive(Debug, Clone)]
+pub struct CompositeCodec<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A: ByteCodec, B: ByteCodec> CompositeCodec<A, B> {
+    /// Composes two codecs, so `first` is applied to an element's bytes before `second`.
+    pub fn new(first: A, second: B) -> Self {
+        Self { first, second }
+    }
+}
+
+impl<A: ByteCodec, B: ByteCodec> ByteCodec for CompositeCodec<A, B> {
+    fn encode(&self, bytes: &[u8]) -> Result<Vec<u8>, HashQueueError> {
+        self.second.encode(&self.first.encode(bytes)?)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<Vec<u8>, HashQueueError> {
+        self.first.decode(&self.second.decode(bytes)?)
+    }
+}
@@ -5,13 +5,44 @@ use sled::Error;
 #[derive(Debug)]
 pub enum HashQueueError {
     SledError{
-        message:  String
+        error: Error
     },
     SyncError{
         message: String
     },
     BinCodeError {
         error: ErrorKind
+    },
+    IndexOverflow {
+        index: i64
+    },
+    InvalidKey {
+        key: Vec<u8>
+    },
+    IoError {
+        error: std::io::Error
+    },
+    TruncatedFrame {
+        frame: usize
+    },
+    Unsupported {
+        message: String
+    },
+    ElementTooLarge {
+        size: usize,
+        max: usize
+    },
+    UnknownCheckpoint {
+        id: u64
+    },
+    CapacityExceeded {
+        capacity: usize
+    },
+    JsonError {
+        error: serde_json::Error
+    },
+    UnexpectedDefaultTreeData {
+        name: String
     }
 }
 impl Display for HashQueueError {
@@ -20,19 +51,60 @@ impl Display for HashQueueError {
             HashQueueError::SyncError{ message} => {
                 write!(f, "Sled and the HashSet fell out of sync, the data structure is no longer valid: {}", message)
             }
-            HashQueueError::SledError{ message} => {
-                write!(f, "Failed to acquire lock: {}", message)
+            HashQueueError::SledError{ error} => {
+                write!(f, "Sled failed to complete the operation: {}", error)
             }
             HashQueueError::BinCodeError { error } => {
                 write!(f, "Failed to deserialize data: {}", error)
             }
+            HashQueueError::IndexOverflow { index } => {
+                write!(f, "No index is left to push to past {}", index)
+            }
+            HashQueueError::InvalidKey { key } => {
+                write!(f, "The tree contains a key that isn't a queue index: {:?}", key)
+            }
+            HashQueueError::IoError { error } => {
+                write!(f, "Failed to read or write a stream: {}", error)
+            }
+            HashQueueError::TruncatedFrame { frame } => {
+                write!(f, "The stream ended part way through frame {}", frame)
+            }
+            HashQueueError::Unsupported { message } => {
+                write!(f, "The operation isn't supported by this queue: {}", message)
+            }
+            HashQueueError::ElementTooLarge { size, max } => {
+                write!(f, "The element serializes to {} bytes, which is more than the maximum of {} bytes", size, max)
+            }
+            HashQueueError::UnknownCheckpoint { id } => {
+                write!(f, "The queue has no checkpoint with id {}", id)
+            }
+            HashQueueError::CapacityExceeded { capacity } => {
+                write!(f, "The queue is already holding its capacity of {} elements", capacity)
+            }
+            HashQueueError::JsonError { error } => {
+                write!(f, "Failed to encode or decode JSON: {}", error)
+            }
+            HashQueueError::UnexpectedDefaultTreeData { name } => {
+                write!(f, "The database's default tree holds data, but the queue is stored in the tree {:?}", name)
+            }
+        }
+    }
+}
+impl std::error::Error for HashQueueError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            HashQueueError::SledError { error } => Some(error),
+            HashQueueError::BinCodeError { error } => Some(error),
+            HashQueueError::IoError { error } => Some(error),
+            HashQueueError::JsonError { error } => Some(error),
+            _ => None,
         }
     }
 }
 impl From<Error> for HashQueueError {
     fn from(error: Error) -> Self {
         HashQueueError::SledError{
-            message: error.to_string()
+            error
         }
     }
 }
@@ -42,4 +114,18 @@ impl From<Box<ErrorKind>> for HashQueueError {
             error: *value
         }
     }
-}
\ No newline at end of file
+}
+impl From<std::io::Error> for HashQueueError {
+    fn from(error: std::io::Error) -> Self {
+        HashQueueError::IoError {
+            error
+        }
+    }
+}
+impl From<serde_json::Error> for HashQueueError {
+    fn from(error: serde_json::Error) -> Self {
+        HashQueueError::JsonError {
+            error
+        }
+    }
+}
@@ -1,20 +1,185 @@
 use std::hash::{Hash};
-use std::collections::HashSet;
-use std::fmt::Debug;
-use std::ops::Deref;
-use std::path::Path;
+use std::io::{ErrorKind, Read, Write};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Debug, Display, Formatter};
+use std::marker::PhantomData;
+use std::ops::{Bound, Deref};
+use std::fs::{File, TryLockError};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use bincode;
 use serde::{Deserialize, Serialize};
-use sled::{self, Error, IVec, Tree};
+use sled::{self, Batch, Db, Error, Event, IVec, Transactional, Tree};
+use sled::transaction::{ConflictableTransactionError, TransactionError, TransactionResult};
 
 use crate::errors::HashQueueError;
+use crate::options::{CorruptPolicy, DedupMode, HashQueueOptions};
+use crate::telemetry;
+
+//The suffix added to the queue's name to name the tree holding the set snapshot, and the keys used within that tree.
+const SNAPSHOT_TREE_SUFFIX: &[u8] = b"::set_snapshot";
+const GENERATION_KEY: &[u8] = b"generation";
+const SNAPSHOT_KEY: &[u8] = b"snapshot";
+
+//The suffix added to the queue's name to name the tree holding the time each delayed element becomes visible, keyed by the element's index.
+const DELAYS_TREE_SUFFIX: &[u8] = b"::delays";
+
+//The suffix added to the queue's name to name the tree holding the time each element pushed with a TTL expires, keyed by the element's index.
+const EXPIRIES_TREE_SUFFIX: &[u8] = b"::expiries";
+
+//The suffix added to the queue's name to name the tree holding checkpoints, mapping each checkpoint id to the key the next push_back after it would use.
+const CHECKPOINTS_TREE_SUFFIX: &[u8] = b"::checkpoints";
+
+//The suffix added to the queue's name to name the tree corrupt elements are moved to, under `CorruptPolicy::SkipAndQuarantine`.
+const QUARANTINE_TREE_SUFFIX: &[u8] = b"::quarantine";
+
+//How long close will wait for sled to release its file lock.
+const CLOSE_LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+
+//The bit flipped when converting between an index and its key.
+const KEY_SIGN_BIT: u64 = 1 << 63;
+
+//Keys written before push_front existed are the plain big endian index, so they are all below this. In the current encoding, a key this low is an index below
+//`-(1 << 62)`, which push_front would have to be called about 4.6 quintillion times to reach, so a tree whose last key is below this uses the legacy encoding.
+const LEGACY_KEY_LIMIT: u64 = 1 << 62;
+
+//Converts an index into the key it is stored under in the tree. Sled orders keys by their bytes, so the sign bit is flipped to make negative indices,
+//which push_front uses, sort before positive ones. Queues written before push_front existed stored the plain big endian index, they are re-keyed when
+//they are opened, see migrate_legacy_keys.
+pub(crate) fn key_from_index(index: i64) -> [u8; 8] {
+    (index as u64 ^ KEY_SIGN_BIT).to_be_bytes()
+}
+
+//Builds the key an element pushed with `priority` is stored at. The sequence number follows the priority, so elements with the same priority keep the order they were pushed in.
+fn priority_key(priority: i64, sequence: u64) -> [u8; 16] {
+    let mut key = [0u8; 16];
+    key[..8].copy_from_slice(&key_from_index(priority));
+    key[8..].copy_from_slice(&sequence.to_be_bytes());
+    key
+}
+
+//Converts a key in the tree back into the index it was inserted at. For a priority key, this is the priority.
+pub(crate) fn index_from_key(key: &[u8]) -> i64 {
+    (u64::from_be_bytes(
+        key[..8]
+            .try_into()
+            .expect("index_from_key: couldn't convert key to bytes"),
+    ) ^ KEY_SIGN_BIT) as i64
+}
+
+//Reads from `reader` until `buf` is full or the reader runs out, returning how many bytes were read.
+fn read_until_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(error) if error.kind() == ErrorKind::Interrupted => {}
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(read)
+}
+
+//The current time in milliseconds since the unix epoch.
+pub(crate) fn unix_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_millis() as u64).unwrap_or(0)
+}
+
+///Name: HealthStatus
+///
+/// Desc: The result of `HashQueue::health_check`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HealthStatus {
+    /// Whether the tree could be read.
+    pub reachable: bool,
+    /// Whether the tree holds as many elements as the set tracks. This is a cheap check, it won't catch every way the two can disagree.
+    pub consistent: bool,
+    /// Whether the queue has returned a `HashQueueError::SyncError`, meaning the set and tree were found to be out of sync.
+    pub poisoned: bool,
+}
+
+///Name: QueueStats
+///
+/// Desc: The result of `HashQueue::stats`. Its `Display` implementation gives a one line summary, like `jobs: 3 elements, 0 rejected duplicates, front Some(1), back Some(3)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueueStats<T> {
+    /// The name of the queue.
+    pub name: String,
+    /// How many elements are in the queue. See `HashQueue::len`.
+    pub len: usize,
+    /// How many pushes have been rejected as duplicates since the queue was opened. See `HashQueue::rejected_count`.
+    pub rejected_duplicates: u64,
+    /// The element at the front of the queue, if there is one.
+    pub front: Option<T>,
+    /// The element at the back of the queue, if there is one.
+    pub back: Option<T>,
+}
+
+impl<T: Debug> Display for QueueStats<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.len == 0 {
+            write!(f, "{}: empty, {} rejected duplicates", self.name, self.rejected_duplicates)
+        } else {
+            write!(f, "{}: {} elements, {} rejected duplicates, front {:?}, back {:?}", self.name, self.len, self.rejected_duplicates, self.front, self.back)
+        }
+    }
+}
 
 pub struct HashQueue<T>{
+    //The database the tree belongs to, used to open sidecar trees. This is only known if the queue opened the database itself.
+    db: Option<Db>,
     tree: Tree,
     set: HashSet<T>,
+    //Copies of a value beyond the first one tracked by the set. This is only ever populated when the dedup mode allows a value to be queued more than once.
+    duplicates: HashMap<T, usize>,
+    name: String,
+    //The path the database was opened from, if this queue opened it.
+    path: Option<PathBuf>,
+    options: HashQueueOptions,
+    //The sidecar tree holding a snapshot of the set, when `snapshot_set` is enabled.
+    snapshot: Option<Tree>,
+    //Whether the set has changed since the snapshot was last written. The generation is bumped the first time this becomes true, which marks the snapshot on disk as stale.
+    snapshot_dirty: bool,
+    generation: u64,
+    //The sidecar tree holding when each delayed element becomes visible. This is only opened once an element has been delayed.
+    delays: Option<Tree>,
+    //The sidecar tree holding when each element pushed with a TTL expires. This is only opened once an element has been pushed with a TTL.
+    expiries: Option<Tree>,
+    //Pushes that have been written to the tree, but not yet flushed, when a write buffer is in use.
+    pending_writes: usize,
+    pending_since: Option<Instant>,
+    //Whether operations flush the tree as they finish, see set_autoflush.
+    autoflush: bool,
+    //How many pushes have been rejected as duplicates since the queue was opened, see rejected_count.
+    rejected_duplicates: u64,
+    //Set once the set and tree have been found to be out of sync, see health_check. This is atomic so it can be set through `&self` without making the queue `!Sync`.
+    poisoned: AtomicBool,
+    //The most recently deserialized front element, see front_cached. This is behind a mutex so it can be updated through `&self` without making the queue `!Sync`.
+    front_cache: Mutex<Option<CachedFront<T>>>,
+    #[cfg(test)]
+    forced_back_index: Option<i64>,
+    #[cfg(test)]
+    fail_next_insert: bool,
+    #[cfg(test)]
+    fail_next_flush: bool,
+}
+
+//A deserialized element, along with the key and bytes it was deserialized from. The cache is only valid while the tree still holds exactly these bytes at this key.
+struct CachedFront<T> {
+    key: IVec,
+    val: IVec,
+    item: Arc<T>,
 }
 
+//The queue is meant to be shared between threads behind a lock, like `Arc<RwLock<HashQueue<T>>>`, so this fails to compile if a field stops it being `Send` or `Sync`.
+fn assert_send_sync<Q: Send + Sync>() {}
+const _: fn() = assert_send_sync::<HashQueue<u64>>;
+
 impl<T> HashQueue<T>
     where
         T: Hash + Eq + Clone + Serialize + Debug,
@@ -27,416 +192,6295 @@ impl<T> HashQueue<T>
     ///
     /// Additional notes: If any of the fallible operations in this function fail, this function will return a `HashQueueError`. Therefore, we know
     ///                    that if it doesn't fail, the data structure has been properly initialized, and consistent with the desired properties of the data structure.
+    ///                    The queue never uses the database's default tree, so if that tree holds any data, this function returns `UnexpectedDefaultTreeData`.
+    ///                    A queue written before `push_front` existed, keyed by the plain big endian index, is re-keyed into the current encoding in one transaction.
     ///
     /// Usage:
     ///```
-    /// use std::path::Path;
     /// use set_deque::hash_queue::HashQueue;
     ///
-    /// let mut hash_queue = HashQueue::open(Path::new("./examples/open"), "test").unwrap();
+    /// let dir = tempfile::tempdir().unwrap();
+    /// let mut hash_queue = HashQueue::open(dir.path(), "test").unwrap();
     ///
     /// hash_queue.push_back(1).unwrap();
     ///
     /// let result = hash_queue.front().unwrap();
     ///
     /// assert_eq!(Some(1), result);
-    ///
-
+    /// ```
     pub fn open<P: AsRef<Path>, V: AsRef<[u8]>>(path: P, name: V) -> Result<Self, HashQueueError>{
-        let db = sled::open(path)?;
-
-        //This looks weird, and may be a bit of a hack, but this way we can filter out any errors that happen in iterating over the db and fail if any occur.
-        let collected_iter = db.iter().collect::<Result<Vec<(IVec, IVec)>, Error>>()?;
-
-        let mut set: HashSet<T> = HashSet::new();
-        //After all, we need to be sure the data structures are *always* synced, so we should fail fast.
-        if collected_iter.is_empty(){
-            Ok(Self{
-                tree: db.open_tree(name)?,
-                set
-        })
-        }
-        else{
-            for (_, value) in collected_iter {
-
-                let item = bincode::deserialize(value.as_ref())?; //deserialize the item to store it in the hash set.
-                set.insert(item); //inset the value into the set
-            }
-            Ok(Self{
-                tree: db.open_tree(name)?,
-                set
-            })
-        }
+        Self::open_with_options(path, name, HashQueueOptions::default())
     }
 
-    ///Name: is_empty
+    ///Name: open_with_options
     ///
-    /// Desc: This function uses the cardinality of the hash set to determine if the queue is empty.
+    /// Desc: This function opens a HashQueue the same way `open` does, but with the behavior described by `options`.
+    /// `open` is equivalent to calling this function with `HashQueueOptions::default()`.
     ///
     /// Usage:
     ///```
-    /// use std::path::Path;
+    /// use std::time::Duration;
     /// use set_deque::hash_queue::HashQueue;
+    /// use set_deque::options::{HashQueueOptions, WriteBuffer};
     ///
-    /// let mut hash_queue = HashQueue::open(Path::new("./examples/is_empty"), "test").unwrap();
+    /// let options = HashQueueOptions {
+    ///     write_buffer: Some(WriteBuffer { capacity: 16, max_delay: Duration::from_millis(100) }),
+    ///     ..HashQueueOptions::default()
+    /// };
+    /// let dir = tempfile::tempdir().unwrap();
+    /// let mut hash_queue = HashQueue::open_with_options(dir.path(), "test", options).unwrap();
     ///
     /// hash_queue.push_back(1).unwrap();
     ///
-    /// let result = hash_queue.is_empty();
+    /// assert_eq!(Some(1), hash_queue.front().unwrap());
+    /// ```
+    pub fn open_with_options<P: AsRef<Path>, V: AsRef<[u8]>>(path: P, name: V, options: HashQueueOptions) -> Result<Self, HashQueueError>{
+        let path = path.as_ref().to_path_buf();
+        let db = sled::open(&path)?;
+        Self::check_default_tree(&db, name.as_ref())?;
+        Self::open_db(db, Some(path), name, options)
+    }
+
+    ///Name: open_with_config
     ///
-    /// assert_eq!(false, result);
+    /// Desc: This function opens a HashQueue the same way `open` does, but opens the database with `config` rather than sled's defaults.
+    /// This is intended for tuning sled, for example its `cache_capacity`, `mode`, or `flush_every_ms`. The path to open is set on `config`.
     ///
-    /// let result = hash_queue.pop_front().unwrap();
+    /// Additional notes: `HashQueueOptions::default()` is used for the queue itself. A temporary database is removed once the queue is dropped.
+    ///                   Like `open`, this function returns `UnexpectedDefaultTreeData` if the database's default tree holds any data.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
     ///
+    /// let dir = tempfile::tempdir().unwrap();
+    /// let config = sled::Config::new()
+    ///     .path(dir.path())
+    ///     .cache_capacity(1024 * 1024)
+    ///     .mode(sled::Mode::HighThroughput);
+    /// let mut hash_queue = HashQueue::open_with_config(config, "test").unwrap();
     ///
-    /// let result = hash_queue.is_empty();
+    /// hash_queue.push_back(1).unwrap();
     ///
-    /// assert_eq!(true, result);
+    /// assert_eq!(Some(1), hash_queue.front().unwrap());
     /// ```
-
-    pub fn is_empty(&self) -> bool{
-        self.set.is_empty()
-    }
-
-    ///This function calculates the index at back of the deque.
-    fn back_index(&self) -> i64 {
-        if let Ok(Some((key, _val))) = self.tree.last() {
-            let k = i64::from_be_bytes(
-                key.as_ref()[..8]
-                    .try_into()
-                    .expect("back_index: couldn't convert key to bytes"),
-            );
-            println!("back_index: {}", k);
-            println!("back_index+1: {:?}", k + 1i64);
-            k + 1i64
-        } else {
-            0i64
-        }
+    pub fn open_with_config<V: AsRef<[u8]>>(config: sled::Config, name: V) -> Result<Self, HashQueueError>{
+        let db = config.open()?;
+        //A temporary database has no lock file to wait for in close, since it is removed as soon as it is dropped.
+        let path = (!config.temporary).then(|| config.get_path());
+        Self::check_default_tree(&db, name.as_ref())?;
+        Self::open_db(db, path, name, HashQueueOptions::default())
     }
 
-    ///Name: front
+    ///Name: open_temp
     ///
-    /// Desc: This function returns the front of the queue, if it exists. This is similar to a peek function
-    /// as it will not modify the queue in any way.
+    /// Desc: This function opens an empty HashQueue in a temporary database, at a path sled picks so that it is unique to this call.
+    /// The database is removed once the queue is dropped, so nothing is left on disk. This is intended for tests and examples,
+    /// which can each get a fresh queue without sharing a path, and can run in parallel or repeatedly without seeing each other's elements.
+    ///
+    /// Additional notes: `HashQueueOptions::default()` is used for the queue. This is equivalent to calling `open_with_config` with `sled::Config::new().temporary(true)`.
     ///
     /// Usage:
     ///```
-    /// use std::path::Path;
     /// use set_deque::hash_queue::HashQueue;
     ///
-    /// let mut hash_queue = HashQueue::open(Path::new("./examples/front"), "test").unwrap();
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
     ///
     /// hash_queue.push_back(1).unwrap();
     ///
-    /// let result = hash_queue.front().unwrap();
+    /// assert_eq!(Some(1), hash_queue.front().unwrap());
+    /// ```
+    pub fn open_temp<V: AsRef<[u8]>>(name: V) -> Result<Self, HashQueueError>{
+        Self::open_with_config(sled::Config::new().temporary(true), name)
+    }
+
+    ///Name: from_db
     ///
-    /// assert_eq!(Some(1), result);
+    /// Desc: This function opens the queue named `name` on a database that is already open, so several queues can share one `sled::Db`.
+    /// Sled only lets a path be opened once in a process, so this is how to run more than one queue from the same path. Unlike `from_tree`, the queue gets the
+    /// same sidecar trees as one opened with `open`, so every operation is supported.
     ///
-    /// let result = hash_queue.pop_front().unwrap();
+    /// Additional notes: The database stays open while any queue on it, or the caller's handle, is still alive. `close` flushes the queue, but doesn't wait for the database to close.
+    ///                   Like `open`, this function returns `UnexpectedDefaultTreeData` if the database's default tree holds any data.
     ///
-    /// assert_eq!(Some(1), result);
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
     ///
-    /// let result = hash_queue.is_empty();
+    /// let db = sled::Config::new().temporary(true).open().unwrap();
+    /// let mut first = HashQueue::from_db(&db, "first").unwrap();
+    /// let mut second: HashQueue<i32> = HashQueue::from_db(&db, "second").unwrap();
     ///
-    /// assert_eq!(true, result);
+    /// first.push_back(1).unwrap();
+    ///
+    /// assert_eq!(Some(1), first.front().unwrap());
+    /// assert_eq!(None, second.pop_front().unwrap());
     /// ```
-    pub fn front(&self) -> Result<Option<T>, HashQueueError> {
-        if let Ok(Some((_key, val))) = self.tree.first() {
-            Ok(Some(bincode::deserialize(val.deref())?))
+    pub fn from_db<V: AsRef<[u8]>>(db: &Db, name: V) -> Result<Self, HashQueueError>{
+        Self::check_default_tree(db, name.as_ref())?;
+        Self::open_db(db.clone(), None, name, HashQueueOptions::default())
+    }
+
+    //This is an internal function that refuses a database whose default tree holds data, when the queue is stored in a named tree. The queue never writes to the
+    //default tree, so anything there was written by something else, which usually means the database was opened with the wrong path or the wrong name.
+    fn check_default_tree(db: &Db, name: &[u8]) -> Result<(), HashQueueError>{
+        if name != db.name().as_ref() && !db.is_empty() {
+            return Err(HashQueueError::UnexpectedDefaultTreeData {
+                name: String::from_utf8_lossy(name).into_owned(),
+            });
+        }
+        Ok(())
+    }
+
+    //This is an internal function that opens the named tree, and any sidecar trees, on a database that has already been opened.
+    fn open_db<V: AsRef<[u8]>>(db: Db, path: Option<PathBuf>, name: V, options: HashQueueOptions) -> Result<Self, HashQueueError>{
+        let display_name = String::from_utf8_lossy(name.as_ref()).into_owned();
+
+        let snapshot = if options.snapshot_set {
+            Some(db.open_tree([name.as_ref(), SNAPSHOT_TREE_SUFFIX].concat())?)
         } else {
-            Ok(None)
+            None
+        };
+        let (generation, loaded) = match &snapshot {
+            Some(snapshot) => Self::load_snapshot(snapshot)?,
+            None => (0, None),
+        };
+        //If there was no usable snapshot, the one on disk (if any) is out of date, so it needs to be rewritten on the next flush.
+        let snapshot_dirty = loaded.is_none();
+
+        let tree = db.open_tree(name.as_ref())?;
+        if !options.priority {
+            Self::migrate_legacy_keys(&tree, Some(&db))?;
+        }
+        let (set, duplicates) = match loaded {
+            Some(loaded) => loaded,
+            None => Self::collect_set(&tree, &options)?,
+        };
+        if options.validate_unique && !duplicates.is_empty() {
+            return Err(HashQueueError::SyncError {
+                message: format!("open: {} values are stored more than once", duplicates.len()),
+            });
+        }
+        let delays = Self::open_existing(&db, [name.as_ref(), DELAYS_TREE_SUFFIX].concat())?;
+        let expiries = Self::open_existing(&db, [name.as_ref(), EXPIRIES_TREE_SUFFIX].concat())?;
+        if options.eager_load {
+            for entry in tree.iter() {
+                entry?;
+            }
         }
+        Ok(Self{
+            tree,
+            db: Some(db),
+            set,
+            duplicates,
+            name: display_name,
+            path,
+            options,
+            snapshot,
+            snapshot_dirty,
+            generation,
+            delays,
+            expiries,
+            pending_writes: 0,
+            pending_since: None,
+            autoflush: true,
+            rejected_duplicates: 0,
+            poisoned: AtomicBool::new(false),
+            front_cache: Mutex::new(None),
+            #[cfg(test)]
+            forced_back_index: None,
+            #[cfg(test)]
+            fail_next_insert: false,
+            #[cfg(test)]
+            fail_next_flush: false,
+        })
     }
 
-    ///Name: back
+    ///Name: from_tree
     ///
-    /// Desc: This function returns the back of the queue, if it exists. This is similar to a peek function
-    /// as it will not modify the queue in any way.
+    /// Desc: This function adopts an already open sled tree as a HashQueue, and populates the hashset from the tree's contents.
+    /// This is useful if the tree is shared with other parts of an application, or was configured in a way `open` doesn't support.
+    /// The queue uses the default options, and its name is the name of the tree.
     ///
-    /// Additional Notes: Originally, this was intended to be a Deque, I still plan to make this into one one day, but based on the original use case
-    ///                  there was no need for it to be a deque, so I left it as a queue. making it into a deque might require a refactor as the current
-    ///                  method of indexing the queue is incompatible with a deque.
+    /// Additional notes: Every key in the tree must be an index written by a HashQueue. If a key in the tree isn't, this function will return `HashQueueError::InvalidKey`.
+    ///                    Since the queue rejects duplicates, if the tree stores any value more than once, this function will return `HashQueueError::SyncError`.
+    ///                    Because the queue doesn't own the database, `close` can't wait for its file lock to be released.
+    ///                    Like `open`, a tree written before `push_front` existed is re-keyed into the current encoding in one transaction.
     ///
     /// Usage:
     ///```
-    /// use std::path::Path;
-    /// use std::ptr::hash;
     /// use set_deque::hash_queue::HashQueue;
     ///
-    /// let mut hash_queue = HashQueue::open(Path::new("./examples/back"), "test").unwrap();
+    /// let db = sled::Config::new().temporary(true).open().unwrap();
+    /// let tree = db.open_tree("test").unwrap();
     ///
-    /// hash_queue.push_back(1).unwrap();
-    /// hash_queue.push_back(2).unwrap();
-    /// let result = hash_queue.back().unwrap();
+    /// let mut hash_queue = HashQueue::from_tree(tree).unwrap();
     ///
-    /// assert_eq!(Some(2), result);
+    /// hash_queue.push_back(1).unwrap();
     ///
+    /// assert_eq!(Some(1), hash_queue.front().unwrap());
     /// ```
-    pub fn back(&self) -> Result<Option<T>, HashQueueError> {
-        if let Ok(Some((_key, val))) = self.tree.last() {
-            Ok(Some(bincode::deserialize(val.deref())?))
+    pub fn from_tree(tree: Tree) -> Result<Self, HashQueueError> {
+        Self::migrate_legacy_keys(&tree, None)?;
+        let (set, duplicates) = Self::collect_set(&tree, &HashQueueOptions::default())?;
+        if !duplicates.is_empty() {
+            return Err(HashQueueError::SyncError {
+                message: format!("from_tree: {} values are stored more than once", duplicates.len()),
+            });
+        }
+        Ok(Self{
+            name: String::from_utf8_lossy(&tree.name()).into_owned(),
+            db: None,
+            tree,
+            set,
+            duplicates,
+            path: None,
+            options: HashQueueOptions::default(),
+            snapshot: None,
+            snapshot_dirty: false,
+            generation: 0,
+            delays: None,
+            expiries: None,
+            pending_writes: 0,
+            pending_since: None,
+            autoflush: true,
+            rejected_duplicates: 0,
+            poisoned: AtomicBool::new(false),
+            front_cache: Mutex::new(None),
+            #[cfg(test)]
+            forced_back_index: None,
+            #[cfg(test)]
+            fail_next_insert: false,
+            #[cfg(test)]
+            fail_next_flush: false,
+        })
+    }
+
+    //This is an internal function that re-keys a tree written before push_front existed, whose keys are the plain big endian index, into the current encoding.
+    //Without this, the front of the queue would sit just above `i64::MIN`, so push_front could never succeed. The sidecar trees that refer to the queue's keys, if `db`
+    //is known and they exist, are re-keyed in the same transaction, so the queue is either migrated as a whole or left as it was.
+    fn migrate_legacy_keys(tree: &Tree, db: Option<&Db>) -> Result<(), HashQueueError> {
+        let legacy = match tree.last()? {
+            Some((key, _val)) => key.len() == 8 && u64::from_be_bytes(key[..8].try_into().expect("migrate_legacy_keys: the key is 8 bytes")) < LEGACY_KEY_LIMIT,
+            None => false,
+        };
+        if !legacy {
+            return Ok(());
+        }
+        let rekey = |key: &[u8]| key.try_into().map(|key| key_from_index(i64::from_be_bytes(key))).map_err(|_| HashQueueError::InvalidKey { key: key.to_vec() });
+        let sidecar = |suffix: &[u8]| match db {
+            Some(db) => Self::open_existing(db, [tree.name().as_ref(), suffix].concat()),
+            None => Ok(None),
+        };
+
+        //Each tree is read up front, since a transaction can't iterate. Every rewrite is an optional key to remove, and a key and value to insert. The delays and expiries
+        //are keyed by the element's key, so they move to a new key, while the checkpoints hold an element's key as their value, so they are overwritten in place.
+        let mut trees = vec![tree.clone()];
+        let mut rewrites = vec![Vec::new()];
+        for entry in tree.iter() {
+            let (key, val) = entry?;
+            rewrites[0].push((Some(key.clone()), rekey(&key)?.to_vec(), val));
+        }
+        for suffix in [DELAYS_TREE_SUFFIX, EXPIRIES_TREE_SUFFIX] {
+            if let Some(sidecar) = sidecar(suffix)? {
+                let mut entries = Vec::new();
+                for entry in sidecar.iter() {
+                    let (key, val) = entry?;
+                    entries.push((Some(key.clone()), rekey(&key)?.to_vec(), val));
+                }
+                trees.push(sidecar);
+                rewrites.push(entries);
+            }
+        }
+        if let Some(checkpoints) = sidecar(CHECKPOINTS_TREE_SUFFIX)? {
+            let mut entries = Vec::new();
+            for entry in checkpoints.iter() {
+                let (key, val) = entry?;
+                entries.push((None, key.to_vec(), IVec::from(&rekey(&val)?[..])));
+            }
+            trees.push(checkpoints);
+            rewrites.push(entries);
+        }
+
+        let result: TransactionResult<(), ()> = trees.as_slice().transaction(|trees| {
+            for (tree, entries) in trees.iter().zip(&rewrites) {
+                for (old_key, new_key, val) in entries {
+                    if let Some(old_key) = old_key {
+                        tree.remove(old_key)?;
+                    }
+                    tree.insert(new_key.as_slice(), val)?;
+                }
+            }
+            Ok(())
+        });
+        match result {
+            Ok(()) => {}
+            Err(TransactionError::Storage(error)) => return Err(error.into()),
+            Err(TransactionError::Abort(())) => unreachable!("migrate_legacy_keys: the transaction never aborts"),
+        }
+        tree.flush()?;
+        Ok(())
+    }
+
+    //This is an internal function that opens a sidecar tree, only if it has already been created.
+    fn open_existing(db: &Db, name: Vec<u8>) -> Result<Option<Tree>, HashQueueError> {
+        if db.tree_names().iter().any(|tree_name| tree_name.as_ref() == name.as_slice()) {
+            Ok(Some(db.open_tree(name)?))
         } else {
             Ok(None)
         }
     }
 
-    ///Name: pop_front
+    //This is an internal function that deserializes every value in the tree to build the set, counting any value that appears more than once as a duplicate.
+    #[allow(clippy::type_complexity)]
+    fn collect_set(tree: &Tree, options: &HashQueueOptions) -> Result<(HashSet<T>, HashMap<T, usize>), HashQueueError> {
+        //This looks weird, and may be a bit of a hack, but this way we can filter out any errors that happen in iterating over the db and fail if any occur.
+        let collected_iter = tree.iter().collect::<Result<Vec<(IVec, IVec)>, Error>>()?;
+
+        let mut set: HashSet<T> = HashSet::new();
+        let mut duplicates: HashMap<T, usize> = HashMap::new();
+        //After all, we need to be sure the data structures are *always* synced, so we should fail fast.
+        for (key, value) in collected_iter {
+            if key.len() != options.key_len() {
+                return Err(HashQueueError::InvalidKey { key: key.to_vec() });
+            }
+
+            let item: T = options.decode(value.as_ref())?; //deserialize the item to store it in the hash set.
+            if !set.insert(item.clone()) { //inset the value into the set
+                *duplicates.entry(item).or_insert(0) += 1;
+            }
+        }
+        Ok((set, duplicates))
+    }
+
+    ///Name: open_bounded
     ///
-    /// Desc: This function returns the front element of the queue, if it exists. This will modify the queue and remove the element.
-    /// If the element doesn't exist, this method will return Ok(None). It will only return a HashQueueError if an error occurs that indicates the data structure is corrupted, or an error that can't be recovered from occurs.
+    /// Desc: This function opens a HashQueue like `open`, but limits it to holding at most `capacity` elements. Once the queue is full, pushes will return
+    /// `HashQueueError::CapacityExceeded` until an element is popped. This is the same as `open_with_options` with only `capacity` set.
+    ///
+    /// Additional notes: Pushing an element that is already in a full queue returns ```Ok(false)``` like any other duplicate, rather than `CapacityExceeded`.
     ///
     /// Usage:
     ///```
-    /// use std::path::Path;
     /// use set_deque::hash_queue::HashQueue;
     ///
-    /// let mut hash_queue = HashQueue::open(Path::new("./examples/pop_front"), "test").unwrap();
+    /// let dir = tempfile::tempdir().unwrap();
+    /// let mut hash_queue = HashQueue::open_bounded(dir.path(), "test", 1).unwrap();
     ///
     /// hash_queue.push_back(1).unwrap();
     ///
+    /// assert_eq!(true, hash_queue.push_back(2).is_err());
+    /// ```
+    pub fn open_bounded<P: AsRef<Path>, V: AsRef<[u8]>>(path: P, name: V, capacity: usize) -> Result<Self, HashQueueError>{
+        let options = HashQueueOptions {
+            capacity: Some(capacity),
+            ..HashQueueOptions::default()
+        };
+        Self::open_with_options(path, name, options)
+    }
+
+    ///Name: open_priority
     ///
-    /// let result = hash_queue.pop_front().unwrap();
+    /// Desc: This function opens a HashQueue the same way `open` does, but the elements are ordered by priority rather than the order they are pushed in,
+    /// so `pop_front` returns the element with the lowest priority. Elements are pushed with `push_with_priority`. Duplicates are still rejected using the set.
     ///
-    /// assert_eq!(Some(1), result);
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
     ///
-    /// let result = hash_queue.is_empty();
+    /// let dir = tempfile::tempdir().unwrap();
+    /// let mut hash_queue = HashQueue::open_priority(dir.path(), "test").unwrap();
     ///
-    /// assert_eq!(true, result);
+    /// hash_queue.push_with_priority("later".to_string(), 5).unwrap();
+    /// hash_queue.push_with_priority("sooner".to_string(), 1).unwrap();
+    ///
+    /// assert_eq!(Some("sooner".to_string()), hash_queue.pop_front().unwrap());
     /// ```
-    pub fn pop_front(&mut self) -> Result<Option<T>, HashQueueError> {
-        if let Ok(Some((key, val))) = self.tree.pop_min() {
-            let data = bincode::deserialize(val.deref())?;
-            println!("pop_front: {:?}", key);
-            println!("pop_front: {:?}", data);
-            match self.set.remove(&data){
-                true => {
-                    self.tree.flush().unwrap();
-                    Ok(Some(data))
-                },
-                false => {
-                    Ok(None)
-                }
-            }
-        } else {
-            Ok(None)
-        }
+    pub fn open_priority<P: AsRef<Path>, V: AsRef<[u8]>>(path: P, name: V) -> Result<Self, HashQueueError>{
+        let options = HashQueueOptions {
+            priority: true,
+            ..HashQueueOptions::default()
+        };
+        Self::open_with_options(path, name, options)
     }
 
-    ///Name: pop_back
+    ///Name: open_str
     ///
-    /// Desc: This function returns the back element of the queue, if it exists. This will modify the queue and remove the element.
-    /// If the element doesn't exist, this method will return Ok(None). It will only return a HashQueueError if an error occurs that indicates the data structure is corrupted, or an error that can't be recovered from occurs.
+    /// Desc: This function opens a HashQueue whose tree is named by a UTF-8 string. This is the common case, `open` is still available
+    /// for callers that need a binary tree name.
     ///
     /// Usage:
     ///```
-    /// use std::path::Path;
     /// use set_deque::hash_queue::HashQueue;
     ///
-    /// let mut hash_queue = HashQueue::open(Path::new("./examples/pop_back"), "test").unwrap();
+    /// let dir = tempfile::tempdir().unwrap();
+    /// let hash_queue: HashQueue<u64> = HashQueue::open_str(dir.path(), "test").unwrap();
     ///
-    /// hash_queue.push_back(1).unwrap();
-    /// hash_queue.push_back(2).unwrap();
+    /// assert_eq!("test", hash_queue.name());
+    /// ```
+    pub fn open_str<P: AsRef<Path>>(path: P, name: &str) -> Result<Self, HashQueueError>{
+        let mut hash_queue = Self::open(path, name.as_bytes())?;
+        hash_queue.name = name.to_string();
+        Ok(hash_queue)
+    }
+
+    ///Name: name
     ///
-    /// let result = hash_queue.pop_back().unwrap();
+    /// Desc: This function returns the name of the tree backing the queue. If the queue was opened with a binary name via `open`,
+    /// any invalid UTF-8 is replaced with `U+FFFD`.
     ///
-    /// assert_eq!(Some(2), result);
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let hash_queue: HashQueue<u64> = HashQueue::open_temp("test").unwrap();
+    ///
+    /// assert_eq!("test", hash_queue.name());
     /// ```
-    pub fn pop_back (&mut self) -> Result<Option<T>, HashQueueError> {
-        if let Ok(Some((key, val))) = self.tree.pop_max() {
-            let data = bincode::deserialize(val.deref())?;
-            println!("pop_back: {:?}", key);
-            println!("pop_back: {:?}", data);
-            match self.set.remove(&data){
-                true => {
-                    self.tree.flush().unwrap();
-                    Ok(Some(data))
-                },
-                false => {
-                    Err(HashQueueError::SyncError {
-                        message: "pop_back".to_string(),
-                    })
-                }
-            }
-        } else {
-            Ok(None)
-        }
+    pub fn name(&self) -> &str {
+        &self.name
     }
 
-    //This is an internal function that is used to insert an item to the sled db at a given index.
-    fn insert_at(&mut self, value: T, n: i64) -> Result<bool, HashQueueError>{
-        println!("insert_at: {}", n);
-        if self.set.insert(value.clone()){
-            self.tree
-                .insert(i64::to_be_bytes(n), bincode::serialize(&value)?)
-                .expect("insert_at: failure to insert");
-            Ok(true)
-        }
-        else{
-            Ok(false)
-        }
+    ///Name: len
+    ///
+    /// Desc: This function returns how many elements are in the queue, using the cardinality of the hash set rather than reading the tree, so it is O(1).
+    /// Copies of an element that is queued more than once, like with `push_back_force`, `DedupMode::AdjacentOnly` or `DedupMode::Off`, are each counted, which adds a step per element with extra copies.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    /// hash_queue.push_back(2).unwrap();
+    ///
+    /// assert_eq!(2, hash_queue.len());
+    /// ```
+    pub fn len(&self) -> usize {
+        self.item_count()
     }
 
-    ///Name: push_back
+    ///Name: is_empty
     ///
-    /// Desc: This function pushes an element to the back of the queue. This will modify the queue.
-    /// If the element isn't already present in the queue, this method will return ```Ok(true)```, and modify the queue to include the element. If the element is already present, it will return ```Ok(false)```
-    /// It will only return a HashQueueError if an error occurs that indicates the data structure is corrupted, or an error that can't be recovered from occurs.
+    /// Desc: This function uses the cardinality of the hash set to determine if the queue is empty. It is defined in terms of `len`, so the two always agree.
     ///
     /// Usage:
     ///```
-    /// use std::path::Path;
     /// use set_deque::hash_queue::HashQueue;
     ///
-    /// let mut hash_queue = HashQueue::open(Path::new("./examples/push_back"), "test").unwrap();
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
     ///
     /// hash_queue.push_back(1).unwrap();
     ///
+    /// let result = hash_queue.is_empty();
+    ///
+    /// assert_eq!(false, result);
     ///
     /// let result = hash_queue.pop_front().unwrap();
     ///
-    /// assert_eq!(Some(1), result);
     ///
     /// let result = hash_queue.is_empty();
     ///
     /// assert_eq!(true, result);
     /// ```
-    pub fn push_back(&mut self, value: T) -> Result<bool, HashQueueError>{
-        let last = self.back_index();
-        let return_value = self.insert_at(value, last );
-        self.tree.flush().expect("push_back: failure to flush tree");
-        return_value
+    pub fn is_empty(&self) -> bool{
+        self.len() == 0
     }
 
-    ///Name: clear
+    ///Name: contains
     ///
-    /// Desc: This function removes all of the data from the data structure. This includes the file backed db.
-    /// Only use it if you intend to remove the data.
+    /// Desc: This function reports whether `value` is in the queue. It checks the hash set rather than reading the tree, so it is O(1).
     ///
     /// Usage:
     ///```
-    /// use std::path::Path;
     /// use set_deque::hash_queue::HashQueue;
     ///
-    /// let mut hash_queue = HashQueue::open(Path::new("./examples/clear"), "test").unwrap();
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
     ///
     /// hash_queue.push_back(1).unwrap();
     ///
-    /// hash_queue.clear();
+    /// assert_eq!(true, hash_queue.contains(&1));
+    /// assert_eq!(false, hash_queue.contains(&2));
+    /// ```
+    pub fn contains(&self, value: &T) -> bool {
+        self.set.contains(value)
+    }
+
+    ///Name: contains_all
+    ///
+    /// Desc: This function reports whether every element of `items` is in the queue, checking each against the hash set like `contains`.
+    /// It stops at the first element that isn't in the queue. If `items` is empty, this returns true.
     ///
-    /// let result = hash_queue.is_empty();
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
     ///
-    /// assert_eq!(true, result);
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    /// hash_queue.push_back(2).unwrap();
+    ///
+    /// assert_eq!(true, hash_queue.contains_all(&[1, 2]));
+    /// assert_eq!(false, hash_queue.contains_all(&[1, 3]));
     /// ```
-    pub fn clear(&mut self) {
-        self.tree.clear().expect("clear: failure to clear tree");
-        self.set.clear();
+    pub fn contains_all<'a, I: IntoIterator<Item = &'a T>>(&self, items: I) -> bool where T: 'a {
+        items.into_iter().all(|item| self.set.contains(item))
     }
 
-}
-
-#[cfg(test)]
-mod tests{
-    use std::fmt::Debug;
-    use std::hash::Hash;
-    use std::path::Path;
-    use serde::{Deserialize, Serialize};
-    use crate::hash_queue::HashQueue;
-
+    ///Name: contains_any
+    ///
+    /// Desc: This function reports whether any element of `items` is in the queue, checking each against the hash set like `contains`.
+    /// It stops at the first element that is in the queue. If `items` is empty, this returns false.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    ///
+    /// assert_eq!(true, hash_queue.contains_any(&[3, 1]));
+    /// assert_eq!(false, hash_queue.contains_any(&[2, 3]));
+    /// ```
+    pub fn contains_any<'a, I: IntoIterator<Item = &'a T>>(&self, items: I) -> bool where T: 'a {
+        items.into_iter().any(|item| self.set.contains(item))
+    }
 
+    ///This function calculates the index at back of the deque. If the back of the deque is already at `i64::MAX` there's no index left to push to,
+    /// so this returns `HashQueueError::IndexOverflow` rather than wrapping around to the front of the tree.
+    fn back_index(&self) -> Result<i64, HashQueueError> {
+        self.check_fifo("push_back")?;
+        #[cfg(test)]
+        if let Some(index) = self.forced_back_index {
+            return Ok(index);
+        }
+        if let Ok(Some((key, _val))) = self.tree.last() {
+            let k = index_from_key(&key);
+            k.checked_add(1i64).ok_or(HashQueueError::IndexOverflow { index: k })
+        } else {
+            Ok(0i64)
+        }
+    }
 
-    /// This function is a basic start up that is used to initialize the set-deque and
-    fn test_setup<T:  Hash + Eq + Clone + Serialize + Debug + for<'de> Deserialize<'de>>(_: T, db_name: &str ) -> HashQueue<T>{
-        let mut set_deque: HashQueue<T> = HashQueue::open(Path::new(db_name), "test").unwrap();
-        set_deque.clear();
-        set_deque
+    ///This function calculates the index in front of the deque, which is one below the front element's index, or `0` if the deque is empty. If the front of the deque
+    /// is already at `i64::MIN` there's no index left to push to, so this returns `HashQueueError::IndexOverflow`.
+    fn front_index(&self) -> Result<i64, HashQueueError> {
+        self.check_fifo("push_front")?;
+        match self.tree.first()? {
+            Some((key, _val)) => {
+                let k = index_from_key(&key);
+                k.checked_sub(1i64).ok_or(HashQueueError::IndexOverflow { index: k })
+            }
+            None => Ok(0i64),
+        }
+    }
+
+    //This is an internal function that rejects anything that needs a position in FIFO order when the queue stores elements by priority instead.
+    fn check_fifo(&self, caller: &str) -> Result<(), HashQueueError> {
+        if self.options.priority {
+            return Err(HashQueueError::Unsupported {
+                message: format!("{}: the queue is ordered by priority, use push_with_priority", caller),
+            });
+        }
+        Ok(())
+    }
+
+    ///Returns the index the next `push_back` will use, so tests can check the indexing scheme directly.
+    #[cfg(test)]
+    pub(crate) fn peek_next_back_index(&self) -> Result<i64, HashQueueError> {
+        self.back_index()
+    }
+
+    ///Overrides the index the next successful `push_back` will use. Later pushes carry on from the forced index.
+    #[cfg(test)]
+    pub(crate) fn force_next_back_index(&mut self, index: i64) {
+        self.forced_back_index = Some(index);
+    }
+
+    ///Makes the next write of a single element to the tree fail with an I/O error, so tests can check nothing else is changed when a write fails.
+    #[cfg(test)]
+    pub(crate) fn fail_next_insert(&mut self) {
+        self.fail_next_insert = true;
+    }
+
+    ///Makes the next flush of the tree fail with an I/O error, as it would if the disk were full.
+    #[cfg(test)]
+    pub(crate) fn fail_next_flush(&mut self) {
+        self.fail_next_flush = true;
+    }
+
+    ///Name: front
+    ///
+    /// Desc: This function returns the front of the queue, if it exists. This is similar to a peek function
+    /// as it will not modify the queue in any way.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    ///
+    /// let result = hash_queue.front().unwrap();
+    ///
+    /// assert_eq!(Some(1), result);
+    ///
+    /// let result = hash_queue.pop_front().unwrap();
+    ///
+    /// assert_eq!(Some(1), result);
+    ///
+    /// let result = hash_queue.is_empty();
+    ///
+    /// assert_eq!(true, result);
+    /// ```
+    pub fn front(&self) -> Result<Option<T>, HashQueueError> {
+        if let Ok(Some((_key, val))) = self.first_visible() {
+            Ok(Some(self.decode(val.deref())?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    ///Name: back
+    ///
+    /// Desc: This function returns the back of the queue, if it exists. This is similar to a peek function
+    /// as it will not modify the queue in any way.
+    ///
+    /// Additional Notes: Originally, this was intended to be a Deque. Now that elements can be pushed to either end with `push_back` and `push_front`, it is one.
+    ///
+    /// Usage:
+    ///```
+    /// use std::ptr::hash;
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    /// hash_queue.push_back(2).unwrap();
+    /// let result = hash_queue.back().unwrap();
+    ///
+    /// assert_eq!(Some(2), result);
+    ///
+    /// ```
+    pub fn back(&self) -> Result<Option<T>, HashQueueError> {
+        if let Ok(Some((_key, val))) = self.tree.last() {
+            Ok(Some(self.decode(val.deref())?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    ///Name: front_raw
+    ///
+    /// Desc: This function returns the bytes stored for the front of the queue, if it exists, without deserializing them. This is intended for forwarding elements
+    /// elsewhere without paying for deserialization, and can't fail because an element doesn't deserialize. Like `front`, delayed elements are skipped, and the queue is not modified.
+    ///
+    /// Additional notes: The bytes are exactly what is stored in the tree. If the queue was opened with `compress` or `timestamps`, they are compressed, or start with the time
+    ///                   the element was pushed, so they aren't what the codec alone would produce.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1u64).unwrap();
+    ///
+    /// assert_eq!(1u64.to_le_bytes(), hash_queue.front_raw().unwrap().unwrap().as_ref());
+    /// ```
+    pub fn front_raw(&self) -> Result<Option<IVec>, HashQueueError> {
+        Ok(self.first_visible()?.map(|(_key, val)| val))
+    }
+
+    ///Name: back_raw
+    ///
+    /// Desc: This function returns the bytes stored for the back of the queue, if it exists, without deserializing them. See `front_raw`.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1u64).unwrap();
+    /// hash_queue.push_back(2u64).unwrap();
+    ///
+    /// assert_eq!(2u64.to_le_bytes(), hash_queue.back_raw().unwrap().unwrap().as_ref());
+    /// ```
+    pub fn back_raw(&self) -> Result<Option<IVec>, HashQueueError> {
+        Ok(self.tree.last()?.map(|(_key, val)| val))
+    }
+
+    ///Name: pop_front
+    ///
+    /// Desc: This function returns the front element of the queue, if it exists. This will modify the queue and remove the element.
+    /// If the element doesn't exist, this method will return Ok(None). It will only return a HashQueueError if an error occurs that indicates the data structure is corrupted, or an error that can't be recovered from occurs.
+    /// If the front element can't be deserialized, what happens depends on the queue's `CorruptPolicy`. By default the pop fails, and the element is left at the front of the queue.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    ///
+    ///
+    /// let result = hash_queue.pop_front().unwrap();
+    ///
+    /// assert_eq!(Some(1), result);
+    ///
+    /// let result = hash_queue.is_empty();
+    ///
+    /// assert_eq!(true, result);
+    /// ```
+    pub fn pop_front(&mut self) -> Result<Option<T>, HashQueueError> {
+        Ok(self.pop_front_entry("pop_front")?.map(|(_val, data)| data))
+    }
+
+    ///Name: pop_front_timed
+    ///
+    /// Desc: This function pops the front of the queue like `pop_front`, and also returns how long the element waited in the queue, from when it was pushed until now.
+    /// This is intended for auditing and latency metrics. The queue must have been opened with `timestamps`, otherwise this returns `HashQueueError::Unsupported` without modifying the queue.
+    ///
+    /// Additional notes: The time is measured with the system clock, so it carries over when the queue is reopened. If the clock has gone backwards since the element
+    ///                   was pushed, the wait is reported as zero.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    /// use set_deque::options::HashQueueOptions;
+    ///
+    /// let dir = tempfile::tempdir().unwrap();
+    /// let options = HashQueueOptions { timestamps: true, ..HashQueueOptions::default() };
+    /// let mut hash_queue = HashQueue::open_with_options(dir.path(), "test", options).unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    ///
+    /// let (result, _waited) = hash_queue.pop_front_timed().unwrap().unwrap();
+    ///
+    /// assert_eq!(1, result);
+    /// ```
+    pub fn pop_front_timed(&mut self) -> Result<Option<(T, Duration)>, HashQueueError> {
+        if !self.options.timestamps {
+            return Err(HashQueueError::Unsupported {
+                message: "pop_front_timed: the queue doesn't store timestamps, open it with the timestamps option".to_string(),
+            });
+        }
+        let Some((val, data)) = self.pop_front_entry("pop_front_timed")? else {
+            return Ok(None);
+        };
+        let pushed_at = self.options.pushed_at(&val).unwrap_or(0);
+        Ok(Some((data, Duration::from_millis(unix_millis().saturating_sub(pushed_at)))))
+    }
+
+    //This is an internal function that pops the front of the queue, and returns the bytes that were stored for it along with the deserialized value.
+    fn pop_front_entry(&mut self, caller: &str) -> Result<Option<(IVec, T)>, HashQueueError> {
+        self.invalidate_snapshot()?;
+        if let Some((val, data)) = self.pop_first_visible()? {
+            match self.release(&data){
+                true => {
+                    self.autoflush()?;
+                    telemetry::record_pop(&self.name, self.item_count());
+                    Ok(Some((val, data)))
+                },
+                false => {
+                    Err(self.desync(caller))
+                }
+            }
+        } else {
+            Ok(None)
+        }
+    }
+
+    ///Name: pop_back
+    ///
+    /// Desc: This function returns the back element of the queue, if it exists. This will modify the queue and remove the element.
+    /// If the element doesn't exist, this method will return Ok(None). It will only return a HashQueueError if an error occurs that indicates the data structure is corrupted, or an error that can't be recovered from occurs.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    /// hash_queue.push_back(2).unwrap();
+    ///
+    /// let result = hash_queue.pop_back().unwrap();
+    ///
+    /// assert_eq!(Some(2), result);
+    /// ```
+    pub fn pop_back (&mut self) -> Result<Option<T>, HashQueueError> {
+        self.invalidate_snapshot()?;
+        if let Ok(Some((key, val))) = self.tree.pop_max() {
+            self.forget_delay(&key)?;
+            let data = self.decode(val.deref())?;
+            match self.release(&data){
+                true => {
+                    self.autoflush()?;
+                    telemetry::record_pop(&self.name, self.item_count());
+                    Ok(Some(data))
+                },
+                false => {
+                    Err(self.desync("pop_back"))
+                }
+            }
+        } else {
+            Ok(None)
+        }
+    }
+
+    //This is an internal function that is used to insert an item to the sled db at the index `next_index` calculates, and track it in the set once it has been written.
+    fn insert_at(&mut self, value: T, next_index: fn(&Self) -> Result<i64, HashQueueError>) -> Result<bool, HashQueueError>{
+        if self.options.dedup == DedupMode::Global && self.set.contains(&value) {
+            self.rejected_duplicates += 1;
+            return Ok(false);
+        }
+        let val = self.encode(&value)?;
+        self.insert_free(&val, next_index)?;
+        match self.options.dedup {
+            DedupMode::Global => {
+                self.set.insert(value);
+            }
+            DedupMode::AdjacentOnly | DedupMode::Off => self.track(value),
+        }
+        Ok(true)
+    }
+
+    //This is an internal function that writes `val` at the index `next_index` calculates, and returns the index. Another handle on the same tree may write to that index
+    //between calculating it and writing to it, so the write only happens if the key is still free, otherwise the index is calculated again from the new front or back.
+    fn insert_free(&mut self, val: &[u8], next_index: fn(&Self) -> Result<i64, HashQueueError>) -> Result<i64, HashQueueError> {
+        loop {
+            let index = next_index(self)?;
+            #[cfg(test)]
+            if std::mem::take(&mut self.fail_next_insert) {
+                return Err(Error::Io(ErrorKind::Other.into()).into());
+            }
+            if self.tree.compare_and_swap(key_from_index(index), None as Option<&[u8]>, Some(val))?.is_ok() {
+                return Ok(index);
+            }
+            #[cfg(test)]
+            {
+                self.forced_back_index = None;
+            }
+        }
+    }
+
+    //This is an internal function that records another copy of a value in the set, counting it as a duplicate if the set already holds it.
+    fn track(&mut self, value: T) {
+        if self.set.contains(&value) {
+            *self.duplicates.entry(value).or_insert(0) += 1;
+        } else {
+            self.set.insert(value);
+        }
+    }
+
+    //This is an internal function that forgets one copy of a value. It returns false if the value wasn't tracked at all, which means the set and tree are out of sync.
+    fn release(&mut self, value: &T) -> bool {
+        match self.duplicates.get_mut(value) {
+            Some(count) => {
+                *count -= 1;
+                if *count == 0 {
+                    self.duplicates.remove(value);
+                }
+                true
+            }
+            None => self.set.remove(value),
+        }
+    }
+
+    //This is an internal function that marks the queue as poisoned, and builds the error reporting that the set and tree are out of sync.
+    fn desync(&self, message: &str) -> HashQueueError {
+        self.poisoned.store(true, Ordering::Relaxed);
+        HashQueueError::SyncError {
+            message: message.to_string(),
+        }
+    }
+
+    //This is an internal function that rejects a push if the queue is already at its capacity, or the element is larger than the queue's `max_element_bytes` once serialized.
+    //A duplicate isn't held to the capacity, since the caller rejects it without adding anything to the queue.
+    fn check_push(&self, value: &T) -> Result<(), HashQueueError> {
+        if let Some(capacity) = self.options.capacity {
+            let duplicate = self.options.dedup == DedupMode::Global && self.set.contains(value);
+            if self.len() >= capacity && !duplicate {
+                return Err(HashQueueError::CapacityExceeded { capacity });
+            }
+        }
+        if self.options.max_element_bytes.is_some() {
+            self.check_size(self.encode(value)?.len())?;
+        }
+        Ok(())
+    }
+
+    //This is an internal function that rejects an encoded element of `size` bytes if it is larger than the queue's `max_element_bytes`.
+    fn check_size(&self, size: usize) -> Result<(), HashQueueError> {
+        match self.options.max_element_bytes {
+            Some(max) if size > max => Err(HashQueueError::ElementTooLarge { size, max }),
+            _ => Ok(()),
+        }
+    }
+
+    //These are internal functions that turn an element into the bytes stored in the tree and back, using the queue's codec and compression.
+    fn encode(&self, value: &T) -> Result<Vec<u8>, HashQueueError> {
+        self.options.encode(value)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<T, HashQueueError> {
+        self.options.decode(bytes)
+    }
+
+    //This is an internal function that counts every queued element, including duplicates.
+    fn item_count(&self) -> usize {
+        self.set.len() + self.duplicates.values().sum::<usize>()
+    }
+
+    //This is an internal function that returns the first entry in the tree that isn't delayed. Without any delayed elements, this is just the first entry.
+    fn first_visible(&self) -> Result<Option<(IVec, IVec)>, HashQueueError> {
+        Ok(self.scan_front()?.0)
+    }
+
+    //This is an internal function that finds the first entry in the tree that is neither delayed nor expired, along with every expired entry in front of it.
+    #[allow(clippy::type_complexity)]
+    fn scan_front(&self) -> Result<(Option<(IVec, IVec)>, Vec<(IVec, IVec)>), HashQueueError> {
+        let delays = self.delays.as_ref().filter(|delays| !delays.is_empty());
+        let expiries = self.expiries.as_ref().filter(|expiries| !expiries.is_empty());
+        if delays.is_none() && expiries.is_none() {
+            return Ok((self.tree.first()?, Vec::new()));
+        }
+        let now = unix_millis();
+        let mut expired = Vec::new();
+        for entry in self.tree.iter() {
+            let (key, val) = entry?;
+            if let Some(delays) = delays {
+                if Self::stamp(delays, &key)?.is_some_and(|visible_at| visible_at > now) {
+                    continue;
+                }
+            }
+            if let Some(expiries) = expiries {
+                if Self::stamp(expiries, &key)?.is_some_and(|expires_at| expires_at <= now) {
+                    expired.push((key, val));
+                    continue;
+                }
+            }
+            return Ok((Some((key, val)), expired));
+        }
+        Ok((None, expired))
+    }
+
+    //This is an internal function that reads the time stored for `key` in a sidecar tree, in milliseconds since the unix epoch.
+    fn stamp(sidecar: &Tree, key: &[u8]) -> Result<Option<u64>, HashQueueError> {
+        Ok(sidecar.get(key)?.map(|stamp| u64::from_be_bytes(stamp.as_ref().try_into().expect("stamp: couldn't convert time to bytes"))))
+    }
+
+    //This is an internal function that removes every expired entry in front of the first visible entry, and returns the first visible entry.
+    fn next_visible(&mut self) -> Result<Option<(IVec, IVec)>, HashQueueError> {
+        let (first, expired) = self.scan_front()?;
+        for (key, val) in expired {
+            self.remove_expired(&key, &val)?;
+        }
+        Ok(first)
+    }
+
+    //This is an internal function that removes an expired entry, if it is unchanged since it was read. It returns whether the entry was removed.
+    fn remove_expired(&mut self, key: &IVec, val: &IVec) -> Result<bool, HashQueueError> {
+        if self.tree.compare_and_swap(key, Some(val), None as Option<&[u8]>)?.is_err() {
+            return Ok(false);
+        }
+        self.forget_delay(key)?;
+        //An entry that can't be deserialized was never tracked, so there's nothing to release.
+        if let Ok(item) = self.decode(val) {
+            if !self.release(&item) {
+                return Err(self.desync("remove_expired"));
+            }
+        }
+        telemetry::record_evictions(&self.name, 1, self.item_count());
+        Ok(true)
+    }
+
+    //This is an internal function that removes the first entry in the tree that isn't delayed, and returns the bytes stored for it and its deserialized value.
+    //An entry that can't be deserialized is handled according to the queue's corrupt policy. The entry is only removed if it is unchanged since it was read,
+    //so if another handle on the tree pops it first, this moves on to the next entry.
+    fn pop_first_visible(&mut self) -> Result<Option<(IVec, T)>, HashQueueError> {
+        loop {
+            let Some((key, val)) = self.next_visible()? else {
+                return Ok(None);
+            };
+            match self.decode(val.deref()) {
+                Ok(data) => {
+                    if self.tree.compare_and_swap(&key, Some(&val), None as Option<&[u8]>)?.is_ok() {
+                        self.forget_delay(&key)?;
+                        return Ok(Some((val, data)));
+                    }
+                }
+                Err(error) => self.skip_corrupt(&key, &val, error)?,
+            }
+        }
+    }
+
+    //This is an internal function that removes the first or last entry in the tree, whether or not it's delayed, and returns whether there was one to remove.
+    //Like `pop_first_visible`, the entry is deserialized before it's removed, so an entry that can't be is handled according to the queue's corrupt policy.
+    fn remove_end(&mut self, back: bool, caller: &str) -> Result<bool, HashQueueError> {
+        loop {
+            let entry = if back { self.tree.last()? } else { self.tree.first()? };
+            let Some((key, val)) = entry else {
+                return Ok(false);
+            };
+            match self.decode(val.deref()) {
+                Ok(data) => {
+                    if self.tree.compare_and_swap(&key, Some(&val), None as Option<&[u8]>)?.is_ok() {
+                        self.forget_delay(&key)?;
+                        if !self.release(&data) {
+                            return Err(self.desync(caller));
+                        }
+                        return Ok(true);
+                    }
+                }
+                Err(error) => self.skip_corrupt(&key, &val, error)?,
+            }
+        }
+    }
+
+    //This is an internal function that applies the queue's corrupt policy to an entry that couldn't be deserialized. With `CorruptPolicy::Fail` the entry is left in place and the error is returned.
+    fn skip_corrupt(&mut self, key: &IVec, val: &IVec, error: HashQueueError) -> Result<(), HashQueueError> {
+        match self.options.corrupt_policy {
+            CorruptPolicy::Fail => Err(error),
+            CorruptPolicy::SkipAndDelete => {
+                if self.tree.compare_and_swap(key, Some(val), None as Option<&[u8]>)?.is_ok() {
+                    self.forget_delay(key)?;
+                }
+                Ok(())
+            }
+            CorruptPolicy::SkipAndQuarantine => self.quarantine(key, val),
+        }
+    }
+
+    //This is an internal function that removes up to `n` elements from one end of the queue for `drop_front` and `truncate_back`. The elements removed before an error
+    //stay removed, so the tree is still flushed and the evictions recorded before the error is returned.
+    fn remove_ends(&mut self, n: usize, back: bool, caller: &str) -> Result<usize, HashQueueError> {
+        self.invalidate_snapshot()?;
+        let mut removed = 0;
+        let mut result = Ok(());
+        while removed < n {
+            match self.remove_end(back, caller) {
+                Ok(true) => removed += 1,
+                Ok(false) => break,
+                Err(error) => {
+                    result = Err(error);
+                    break;
+                }
+            }
+        }
+        self.autoflush()?;
+        telemetry::record_evictions(&self.name, removed, self.item_count());
+        result.map(|()| removed)
+    }
+
+    //This is an internal function that moves a corrupt entry out of the tree into the quarantine tree, if the entry is unchanged since it was read.
+    fn quarantine(&mut self, key: &IVec, val: &IVec) -> Result<(), HashQueueError> {
+        let Some(db) = &self.db else {
+            return Err(HashQueueError::Unsupported {
+                message: "quarantining elements needs the queue to have opened its own database".to_string(),
+            });
+        };
+        let quarantine = db.open_tree([self.tree.name().as_ref(), QUARANTINE_TREE_SUFFIX].concat())?;
+        let id = db.generate_id()?.to_be_bytes();
+        let result: TransactionResult<(), ()> = (&self.tree, &quarantine).transaction(|(tree, quarantine)| {
+            if tree.get(key)?.as_ref() == Some(val) {
+                tree.remove(key)?;
+                quarantine.insert(&id, val)?;
+            }
+            Ok(())
+        });
+        match result {
+            Ok(()) => {}
+            Err(TransactionError::Storage(error)) => return Err(error.into()),
+            Err(TransactionError::Abort(())) => unreachable!("quarantine: the transaction never aborts"),
+        }
+        self.forget_delay(key)
+    }
+
+    //This is an internal function that must be called whenever an entry is removed from the tree, so that the index can't inherit the delay or expiry if it's reused.
+    fn forget_delay(&self, key: &[u8]) -> Result<(), HashQueueError> {
+        if let Some(delays) = &self.delays {
+            delays.remove(key)?;
+        }
+        if let Some(expiries) = &self.expiries {
+            expiries.remove(key)?;
+        }
+        Ok(())
+    }
+
+    ///Name: push_back
+    ///
+    /// Desc: This function pushes an element to the back of the queue. This will modify the queue.
+    /// If the element isn't already present in the queue, this method will return ```Ok(true)```, and modify the queue to include the element. If the element is already present, it will return ```Ok(false)```
+    /// It will only return a HashQueueError if an error occurs that indicates the data structure is corrupted, or an error that can't be recovered from occurs.
+    /// When the queue was opened with `DedupMode::AdjacentOnly`, the element is only rejected if it is equal to the current back of the queue. With `DedupMode::Off`, it is never rejected.
+    /// If the queue was opened with `max_element_bytes`, and the element is larger than that once serialized, this will return `HashQueueError::ElementTooLarge` without modifying the queue.
+    /// If the queue was opened with a capacity, and is already full, this will return `HashQueueError::CapacityExceeded` without modifying the queue.
+    ///
+    /// Additional notes: The element is only written if no element is stored at the index after the current back. If another handle on the same tree pushed
+    ///                   to that index first, the back is read again and the element goes after the other handle's push, so neither push overwrites the other.
+    ///                   The set of each handle only tracks its own pushes, so two handles can still push the same value.
+    ///                   If flushing the tree fails, for example because the disk is full, this returns the `SledError` rather than panicking. The element has
+    ///                   already been written to the tree and the set by then, so it is in the queue, but it may not be on disk if the process stops before the next successful flush.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    ///
+    ///
+    /// let result = hash_queue.pop_front().unwrap();
+    ///
+    /// assert_eq!(Some(1), result);
+    ///
+    /// let result = hash_queue.is_empty();
+    ///
+    /// assert_eq!(true, result);
+    /// ```
+    pub fn push_back(&mut self, value: T) -> Result<bool, HashQueueError>{
+        self.check_push(&value)?;
+        if self.options.dedup == DedupMode::AdjacentOnly && self.back()?.as_ref() == Some(&value) {
+            self.rejected_duplicates += 1;
+            return Ok(false);
+        }
+        self.invalidate_snapshot()?;
+        let return_value = self.insert_at(value, Self::back_index);
+        #[cfg(test)]
+        if let Ok(true) = return_value {
+            self.forced_back_index = None;
+        }
+        self.finish_push(matches!(return_value, Ok(true)))?;
+        return_value
+    }
+
+    ///Name: push_front
+    ///
+    /// Desc: This function pushes an element to the front of the queue, so it will be the next element popped from the front. This will modify the queue.
+    /// Like `push_back`, if the element isn't already present in the queue this method will return ```Ok(true)```, and if it is already present it will return ```Ok(false)```.
+    /// When the queue was opened with `DedupMode::AdjacentOnly`, the element is only rejected if it is equal to the current front of the queue. With `DedupMode::Off`, it is never rejected.
+    /// If the queue was opened with `max_element_bytes`, and the element is larger than that once serialized, this will return `HashQueueError::ElementTooLarge` without modifying the queue.
+    /// If the queue was opened with a capacity, and is already full, this will return `HashQueueError::CapacityExceeded` without modifying the queue.
+    ///
+    /// Additional notes: Elements pushed to the front are stored at negative indices. A queue written before `push_front` existed starts at the lowest index there is,
+    ///                   so there's no index left in front of it and this will return `HashQueueError::IndexOverflow` until the queue has been emptied.
+    ///                   Like `push_back`, if another handle on the same tree pushed to the front first, the element goes in front of the other handle's push.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    /// hash_queue.push_front(2).unwrap();
+    ///
+    /// assert_eq!(Some(2), hash_queue.pop_front().unwrap());
+    /// assert_eq!(Some(1), hash_queue.pop_front().unwrap());
+    /// ```
+    pub fn push_front(&mut self, value: T) -> Result<bool, HashQueueError>{
+        self.check_push(&value)?;
+        if self.options.dedup == DedupMode::AdjacentOnly && self.front()?.as_ref() == Some(&value) {
+            self.rejected_duplicates += 1;
+            return Ok(false);
+        }
+        self.invalidate_snapshot()?;
+        let return_value = self.insert_at(value, Self::front_index);
+        self.finish_push(matches!(return_value, Ok(true)))?;
+        return_value
+    }
+
+    //This is an internal function that flushes a push to disk, or counts it towards the write buffer if one is in use, and records it.
+    fn finish_push(&mut self, inserted: bool) -> Result<(), HashQueueError> {
+        if !inserted {
+            return Ok(());
+        }
+        match self.options.write_buffer {
+            Some(write_buffer) => {
+                self.pending_writes += 1;
+                let pending_since = *self.pending_since.get_or_insert_with(Instant::now);
+                if self.pending_writes >= write_buffer.capacity || pending_since.elapsed() >= write_buffer.max_delay {
+                    self.autoflush()?;
+                }
+            }
+            None => {
+                self.autoflush()?;
+            }
+        }
+        telemetry::record_push(&self.name, self.item_count());
+        Ok(())
+    }
+
+    ///Name: clear
+    ///
+    /// Desc: This function removes all of the data from the data structure. This includes the file backed db.
+    /// Only use it if you intend to remove the data. If the tree can't be cleared, this will return a HashQueueError, and the set is left as it was.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    ///
+    /// hash_queue.clear().unwrap();
+    ///
+    /// let result = hash_queue.is_empty();
+    ///
+    /// assert_eq!(true, result);
+    /// ```
+    pub fn clear(&mut self) -> Result<(), HashQueueError> {
+        self.invalidate_snapshot()?;
+        self.tree.clear()?;
+        if let Some(delays) = &self.delays {
+            delays.clear()?;
+        }
+        if let Some(expiries) = &self.expiries {
+            expiries.clear()?;
+        }
+        let evicted = self.item_count();
+        self.set.clear();
+        self.duplicates.clear();
+        telemetry::record_evictions(&self.name, evicted, 0);
+        Ok(())
+    }
+
+    ///Name: fold
+    ///
+    /// Desc: This function folds over every element of the queue from front to back without collecting them into a vector first.
+    /// The queue is not modified. Each element is deserialized as it is visited, so a failure part way through will return a `HashQueueError`.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1u64).unwrap();
+    /// hash_queue.push_back(2u64).unwrap();
+    ///
+    /// let result = hash_queue.fold(0, |sum, item| sum + item).unwrap();
+    ///
+    /// assert_eq!(3, result);
+    /// ```
+    pub fn fold<B, F: FnMut(B, T) -> B>(&self, init: B, mut f: F) -> Result<B, HashQueueError> {
+        let mut accumulator = init;
+        for entry in self.tree.iter() {
+            let (_key, val) = entry?;
+            accumulator = f(accumulator, self.decode(val.deref())?);
+        }
+        Ok(accumulator)
+    }
+
+    ///Name: replace
+    ///
+    /// Desc: This function replaces `old` with `new` in place, so `new` takes over the position `old` held in the queue.
+    /// If `old` isn't present, or `new` is already present elsewhere in the queue, the queue is left unchanged and this method returns ```Ok(false)```.
+    /// The tree entry is swapped with a compare and swap, so the write only lands if the stored value is still `old`.
+    ///
+    /// Additional notes: If `new` is larger than `max_element_bytes` once serialized, this method returns `HashQueueError::ElementTooLarge` and `old` is left in place.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    /// hash_queue.push_back(2).unwrap();
+    ///
+    /// let result = hash_queue.replace(&1, 3).unwrap();
+    ///
+    /// assert_eq!(true, result);
+    /// assert_eq!(Some(3), hash_queue.front().unwrap());
+    /// ```
+    pub fn replace(&mut self, old: &T, new: T) -> Result<bool, HashQueueError> {
+        if !self.set.contains(old) || (old != &new && self.set.contains(&new)) {
+            return Ok(false);
+        }
+        let new_val = self.encode(&new)?;
+        self.check_size(new_val.len())?;
+        for entry in self.tree.iter() {
+            let (key, val) = entry?;
+            let item: T = self.decode(val.deref())?;
+            if &item == old {
+                self.invalidate_snapshot()?;
+                let swapped = self.tree.compare_and_swap(key, Some(val), Some(new_val))?;
+                if swapped.is_err() {
+                    return Ok(false);
+                }
+                self.autoflush()?;
+                self.release(old);
+                self.track(new);
+                return Ok(true);
+            }
+        }
+        Err(self.desync("replace"))
+    }
+
+    ///Name: distance
+    ///
+    /// Desc: This function returns the signed difference between the front-relative positions of `a` and `b`.
+    /// The result is positive if `a` is behind `b` in the queue, and negative if it is ahead of it. If either value isn't in the queue,
+    /// this method will return ```Ok(None)``` without scanning the tree.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    /// hash_queue.push_back(2).unwrap();
+    ///
+    /// let result = hash_queue.distance(&2, &1).unwrap();
+    ///
+    /// assert_eq!(Some(1), result);
+    /// ```
+    pub fn distance(&self, a: &T, b: &T) -> Result<Option<i64>, HashQueueError> {
+        if !self.set.contains(a) || !self.set.contains(b) {
+            return Ok(None);
+        }
+        let mut position_a = None;
+        let mut position_b = None;
+        for (position, entry) in self.tree.iter().enumerate() {
+            let (_key, val) = entry?;
+            let item: T = self.decode(val.deref())?;
+            if &item == a {
+                position_a = Some(position as i64);
+            }
+            if &item == b {
+                position_b = Some(position as i64);
+            }
+            if let (Some(position_a), Some(position_b)) = (position_a, position_b) {
+                return Ok(Some(position_a - position_b));
+            }
+        }
+        Err(self.desync("distance"))
+    }
+
+    ///Name: position
+    ///
+    /// Desc: This function returns how many elements are ahead of `value` in the queue, so the front of the queue is at position 0.
+    /// If the value isn't in the queue, this method will return ```Ok(None)``` without scanning the tree. Otherwise the tree is scanned from the front until the value is found.
+    /// Delayed elements are counted. If the queue holds more than one copy of the value, the position of the copy closest to the front is returned.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    /// hash_queue.push_back(2).unwrap();
+    ///
+    /// assert_eq!(Some(1), hash_queue.position(&2).unwrap());
+    /// assert_eq!(None, hash_queue.position(&3).unwrap());
+    /// ```
+    pub fn position(&self, value: &T) -> Result<Option<usize>, HashQueueError> {
+        if !self.set.contains(value) {
+            return Ok(None);
+        }
+        for (position, val) in self.tree.iter().values().enumerate() {
+            let item: T = self.decode(val?.deref())?;
+            if &item == value {
+                return Ok(Some(position));
+            }
+        }
+        Err(self.desync("position"))
+    }
+
+    ///Name: flush
+    ///
+    /// Desc: This function flushes the tree to disk, including any pushes held in the write buffer. When no write buffer is in use and autoflush is on,
+    /// every push is already flushed, so this is only needed to force buffered pushes to disk early, or to finish a bulk load with autoflush off (see `set_autoflush`).
+    /// If the queue was opened with `snapshot_set`, this also writes a snapshot of the set when it has changed since the last one, which costs O(n).
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    ///
+    /// hash_queue.flush().unwrap();
+    /// ```
+    pub fn flush(&mut self) -> Result<(), HashQueueError> {
+        self.flush_pending()?;
+        self.save_snapshot()
+    }
+
+    //This is an internal function that flushes the tree after an operation, unless autoflush has been turned off. See set_autoflush.
+    fn autoflush(&mut self) -> Result<(), HashQueueError> {
+        if self.autoflush {
+            self.flush_pending()?;
+        }
+        Ok(())
+    }
+
+    ///Name: set_autoflush
+    ///
+    /// Desc: This function turns flushing the tree after every operation on or off. Autoflush is on by default. Turning it off makes bulk loads much faster,
+    /// since every push no longer waits for the disk, but nothing is made durable until `flush` is called, the queue is dropped, or sled flushes in the background on its own schedule.
+    /// Turning autoflush back on doesn't flush by itself, so call `flush` when a bulk load is done.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.set_autoflush(false);
+    /// hash_queue.push_back(1).unwrap();
+    /// hash_queue.push_back(2).unwrap();
+    /// hash_queue.flush().unwrap();
+    /// hash_queue.set_autoflush(true);
+    /// ```
+    pub fn set_autoflush(&mut self, enabled: bool) {
+        self.autoflush = enabled;
+    }
+
+    //This is an internal function that flushes the tree, which also makes any buffered pushes durable.
+    fn flush_pending(&mut self) -> Result<(), HashQueueError> {
+        #[cfg(test)]
+        if std::mem::take(&mut self.fail_next_flush) {
+            return Err(Error::Io(ErrorKind::Other.into()).into());
+        }
+        self.tree.flush()?;
+        self.pending_writes = 0;
+        self.pending_since = None;
+        Ok(())
+    }
+
+    //This is an internal function that reads the current generation, and the snapshot of the set if it was written during that generation.
+    #[allow(clippy::type_complexity)]
+    fn load_snapshot(snapshot: &Tree) -> Result<(u64, Option<(HashSet<T>, HashMap<T, usize>)>), HashQueueError> {
+        let generation: u64 = match snapshot.get(GENERATION_KEY)? {
+            Some(bytes) => bincode::deserialize(bytes.deref())?,
+            None => 0,
+        };
+        //A snapshot that can't be read is treated the same as a stale one, since the set can always be rebuilt from the tree.
+        let loaded = snapshot.get(SNAPSHOT_KEY)?
+            .and_then(|bytes| bincode::deserialize::<(u64, HashSet<T>, HashMap<T, usize>)>(bytes.deref()).ok())
+            .filter(|(snapshot_generation, _, _)| *snapshot_generation == generation)
+            .map(|(_, set, duplicates)| (set, duplicates));
+        Ok((generation, loaded))
+    }
+
+    //This is an internal function that must be called before the tree or set is modified. The first modification after the snapshot was written
+    // bumps the generation on disk, so that a crash before the next snapshot leaves the old one marked as stale.
+    fn invalidate_snapshot(&mut self) -> Result<(), HashQueueError> {
+        if let Some(snapshot) = &self.snapshot {
+            if !self.snapshot_dirty {
+                self.generation += 1;
+                snapshot.insert(GENERATION_KEY, bincode::serialize(&self.generation)?)?;
+                snapshot.flush()?;
+                self.snapshot_dirty = true;
+            }
+        }
+        Ok(())
+    }
+
+    //This is an internal function that writes the set to the snapshot tree, tagged with the current generation. It should only be called once the tree has been flushed.
+    fn save_snapshot(&mut self) -> Result<(), HashQueueError> {
+        if let Some(snapshot) = &self.snapshot {
+            if self.snapshot_dirty {
+                snapshot.insert(SNAPSHOT_KEY, bincode::serialize(&(self.generation, &self.set, &self.duplicates))?)?;
+                snapshot.flush()?;
+                self.snapshot_dirty = false;
+            }
+        }
+        Ok(())
+    }
+
+    ///Name: close
+    ///
+    /// Desc: This function flushes the queue to disk, then drops it along with its handle on the database. Unlike relying on `Drop`,
+    /// any flush error is returned to the caller, and once this returns the database's file lock has been released, so the same path can be reopened right away.
+    ///
+    /// Additional notes: Sled releases its file lock from a background thread shortly after the last handle is dropped, so this waits for the lock to be
+    ///                   released, for up to five seconds, before returning. If the database is still open elsewhere (another queue opened from the same handle),
+    ///                   the lock won't be released and this will return a `HashQueueError` once it gives up waiting.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let dir = tempfile::tempdir().unwrap();
+    /// let mut hash_queue = HashQueue::open(dir.path(), "test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    ///
+    /// hash_queue.close().unwrap();
+    ///
+    /// let hash_queue: HashQueue<i32> = HashQueue::open(dir.path(), "test").unwrap();
+    ///
+    /// assert_eq!(Some(1), hash_queue.front().unwrap());
+    /// ```
+    pub fn close(mut self) -> Result<(), HashQueueError> {
+        self.flush()?;
+        let Some(lock_path) = self.path.as_ref().map(|path| path.join("db")) else {
+            return Ok(());
+        };
+        drop(self);
+
+        let started = Instant::now();
+        loop {
+            let lock_file = File::open(&lock_path).map_err(sled::Error::Io)?;
+            match lock_file.try_lock() {
+                Ok(()) => return Ok(()),
+                Err(TryLockError::WouldBlock) if started.elapsed() < CLOSE_LOCK_TIMEOUT => thread::sleep(Duration::from_millis(1)),
+                Err(TryLockError::WouldBlock) => return Err(sled::Error::Io(std::io::ErrorKind::WouldBlock.into()).into()),
+                Err(TryLockError::Error(error)) => return Err(sled::Error::Io(error).into()),
+            }
+        }
+    }
+
+    ///Name: group_by
+    ///
+    /// Desc: This function buckets every element of the queue by the key `f` extracts from it. Within each bucket the elements
+    /// keep the order they have in the queue. The queue is not modified.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    /// hash_queue.push_back(2).unwrap();
+    /// hash_queue.push_back(3).unwrap();
+    ///
+    /// let result = hash_queue.group_by(|item| item % 2 == 0).unwrap();
+    ///
+    /// assert_eq!(Some(&vec![1, 3]), result.get(&false));
+    /// ```
+    pub fn group_by<K: Hash + Eq, F: FnMut(&T) -> K>(&self, mut f: F) -> Result<HashMap<K, Vec<T>>, HashQueueError> {
+        let mut groups: HashMap<K, Vec<T>> = HashMap::new();
+        for entry in self.tree.iter() {
+            let (_key, val) = entry?;
+            let item: T = self.decode(val.deref())?;
+            groups.entry(f(&item)).or_default().push(item);
+        }
+        Ok(groups)
+    }
+
+    ///Name: drop_front
+    ///
+    /// Desc: This function removes up to `n` elements from the front of the queue, discarding them, and returns how many were removed.
+    /// The tree is flushed once after all of the elements are removed, rather than once per element.
+    ///
+    /// Additional notes: Each element is deserialized before it's removed. If one can't be, what happens depends on the queue's `CorruptPolicy`. By default this returns the error,
+    ///                   and the element is left at the front of the queue, along with everything behind it. The elements in front of it stay removed.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    /// hash_queue.push_back(2).unwrap();
+    ///
+    /// let result = hash_queue.drop_front(1).unwrap();
+    ///
+    /// assert_eq!(1, result);
+    /// assert_eq!(Some(2), hash_queue.front().unwrap());
+    /// ```
+    pub fn drop_front(&mut self, n: usize) -> Result<usize, HashQueueError> {
+        self.remove_ends(n, false, "drop_front")
+    }
+
+    ///Name: truncate_front
+    ///
+    /// Desc: An alias for `drop_front`, named for trimming a capped, log style queue.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    /// hash_queue.push_back(2).unwrap();
+    ///
+    /// assert_eq!(2, hash_queue.truncate_front(5).unwrap());
+    /// assert_eq!(true, hash_queue.is_empty());
+    /// ```
+    pub fn truncate_front(&mut self, n: usize) -> Result<usize, HashQueueError> {
+        self.drop_front(n)
+    }
+
+    ///Name: truncate_back
+    ///
+    /// Desc: This function removes up to `n` of the most recently pushed elements from the back of the queue, discarding them, and returns how many were removed.
+    /// Like `drop_front`, the tree is flushed once after all of the elements are removed, rather than once per element,
+    /// and an element that can't be deserialized is handled according to the queue's `CorruptPolicy` before it's removed.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    /// hash_queue.push_back(2).unwrap();
+    ///
+    /// assert_eq!(1, hash_queue.truncate_back(1).unwrap());
+    /// assert_eq!(Some(1), hash_queue.back().unwrap());
+    /// ```
+    pub fn truncate_back(&mut self, n: usize) -> Result<usize, HashQueueError> {
+        self.remove_ends(n, true, "truncate_back")
+    }
+
+    ///Name: is_contiguous
+    ///
+    /// Desc: This function reports whether the indices the elements are stored at form a consecutive range with no holes in it.
+    /// Holes are left behind when an element is removed from the middle of the queue. An empty queue is contiguous.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    /// hash_queue.push_back(2).unwrap();
+    ///
+    /// assert_eq!(true, hash_queue.is_contiguous().unwrap());
+    /// ```
+    pub fn is_contiguous(&self) -> Result<bool, HashQueueError> {
+        let mut previous: Option<i64> = None;
+        for key in self.tree.iter().keys() {
+            let index = index_from_key(&key?);
+            if let Some(previous) = previous {
+                if previous.checked_add(1) != Some(index) {
+                    return Ok(false);
+                }
+            }
+            previous = Some(index);
+        }
+        Ok(true)
+    }
+
+    ///Name: transfer_front_n
+    ///
+    /// Desc: This function moves up to `n` elements from the front of this queue to the back of `dest`, keeping their order, and returns how many were added to `dest`.
+    /// Elements that are already present in `dest` are still removed from this queue, but aren't pushed again, so they don't count towards the result.
+    ///
+    /// Delayed elements and elements pushed with a TTL keep the time they become visible or expire when they're moved.
+    ///
+    /// Additional notes: The two queues are usually separate databases, which sled can't update in a single transaction. To avoid ever losing an element,
+    ///                   `dest` is flushed before anything is removed from this queue, so a crash part way through can leave an element in both queues, but never in neither.
+    ///                   If pushing an element to `dest` fails, the elements in front of it are still moved, and it and the elements behind it stay in this queue.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut source = HashQueue::open_temp("test").unwrap();
+    /// let mut dest = HashQueue::open_temp("test").unwrap();
+    ///
+    /// source.push_back(1).unwrap();
+    /// source.push_back(2).unwrap();
+    ///
+    /// let result = source.transfer_front_n(&mut dest, 1).unwrap();
+    ///
+    /// assert_eq!(1, result);
+    /// assert_eq!(Some(1), dest.back().unwrap());
+    /// assert_eq!(Some(2), source.front().unwrap());
+    /// ```
+    pub fn transfer_front_n(&mut self, dest: &mut HashQueue<T>, n: usize) -> Result<usize, HashQueueError> {
+        let mut entries: Vec<(IVec, T)> = Vec::new();
+        for entry in self.tree.iter().take(n) {
+            let (key, val) = entry?;
+            entries.push((key, self.decode(val.deref())?));
+        }
+
+        //Only the elements that made it into dest are removed from this queue, so if a push fails, nothing is lost and nothing is left in both queues.
+        let mut moved = 0;
+        let mut pushed = 0;
+        let mut result = Ok(());
+        for (key, item) in &entries {
+            match self.transfer_one(dest, key, item.clone()) {
+                Ok(added) => {
+                    moved += usize::from(added);
+                    pushed += 1;
+                }
+                Err(error) => {
+                    result = Err(error);
+                    break;
+                }
+            }
+        }
+        dest.flush_pending()?;
+        let entries = &entries[..pushed];
+
+        self.invalidate_snapshot()?;
+        let mut batch = Batch::default();
+        for (key, _item) in entries {
+            batch.remove(key);
+        }
+        let trees: Vec<Tree> = std::iter::once(&self.tree).chain(&self.delays).chain(&self.expiries).cloned().collect();
+        let removed: TransactionResult<(), ()> = trees.as_slice().transaction(|trees| {
+            for tree in trees {
+                tree.apply_batch(&batch)?;
+            }
+            Ok(())
+        });
+        match removed {
+            Ok(()) => {}
+            Err(TransactionError::Storage(error)) => return Err(error.into()),
+            Err(TransactionError::Abort(())) => unreachable!("transfer_front_n: the transaction never aborts"),
+        }
+        for (_key, item) in entries {
+            if !self.release(item) {
+                return Err(self.desync("transfer_front_n"));
+            }
+            telemetry::record_pop(&self.name, self.item_count());
+        }
+        self.autoflush()?;
+        result.map(|()| moved)
+    }
+
+    //This is an internal function that pushes an element being moved by `transfer_front_n` onto the back of `dest`, along with the time it becomes visible or expires.
+    //The public functions never give an element both, so an element with an expiry is pushed with just that.
+    fn transfer_one(&self, dest: &mut HashQueue<T>, key: &[u8], item: T) -> Result<bool, HashQueueError> {
+        if let Some(expires_at) = self.expiries.as_ref().map(|expiries| Self::stamp(expiries, key)).transpose()?.flatten() {
+            return dest.push_back_stamped(item, Self::open_expiries, expires_at);
+        }
+        if let Some(visible_at) = self.delays.as_ref().map(|delays| Self::stamp(delays, key)).transpose()?.flatten() {
+            return dest.push_back_stamped(item, Self::open_delays, visible_at);
+        }
+        dest.push_back(item)
+    }
+
+    ///Name: byte_size
+    ///
+    /// Desc: This function returns the total size, in bytes, of the serialized elements stored in the queue. This is the logical size of the payload,
+    /// it doesn't include the keys or any of sled's own overhead, so the queue will take up more space than this on disk.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1u64).unwrap();
+    ///
+    /// assert_eq!(8, hash_queue.byte_size().unwrap());
+    /// ```
+    pub fn byte_size(&self) -> Result<u64, HashQueueError> {
+        let mut size = 0;
+        for val in self.tree.iter().values() {
+            size += val?.len() as u64;
+        }
+        Ok(size)
+    }
+
+    ///Name: front_cached
+    ///
+    /// Desc: This function returns the front of the queue like `front`, but keeps the deserialized element around, so calling it again
+    /// without modifying the front of the queue returns the same `Arc` instead of deserializing the element again.
+    ///
+    /// Additional notes: The cache is checked against the key and bytes currently at the front of the tree, so it is invalidated by any change
+    ///                   to the front of the queue, including changes made through another handle on the same tree.
+    ///
+    /// Usage:
+    ///```
+    /// use std::sync::Arc;
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    ///
+    /// let first = hash_queue.front_cached().unwrap().unwrap();
+    /// let second = hash_queue.front_cached().unwrap().unwrap();
+    ///
+    /// assert_eq!(1, *first);
+    /// assert!(Arc::ptr_eq(&first, &second));
+    /// ```
+    pub fn front_cached(&self) -> Result<Option<Arc<T>>, HashQueueError> {
+        //The cache is only ever replaced as a whole, so it is still usable if another thread panicked while holding the lock.
+        let mut cache = self.front_cache.lock().unwrap_or_else(PoisonError::into_inner);
+        let Some((key, val)) = self.first_visible()? else {
+            *cache = None;
+            return Ok(None);
+        };
+        if let Some(cached) = cache.as_ref() {
+            if cached.key == key && cached.val == val {
+                return Ok(Some(cached.item.clone()));
+            }
+        }
+        let item: Arc<T> = Arc::new(self.decode(val.deref())?);
+        *cache = Some(CachedFront { key, val, item: item.clone() });
+        Ok(Some(item))
+    }
+
+    ///Name: drain_to_writer
+    ///
+    /// Desc: This function pops every element off of the front of the queue and writes it to `writer`, returning how many elements were written.
+    /// Each element is written as a frame, its serialized length as a big endian `u64`, followed by the serialized element. Only one element is held in memory at a time.
+    ///
+    /// Additional notes: An element is only removed from the queue after its frame has been written, so if writing fails the element stays at the front of the queue.
+    ///                   The tree is flushed once, after the queue has been drained.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1u64).unwrap();
+    ///
+    /// let mut buffer = Vec::new();
+    /// let result = hash_queue.drain_to_writer(&mut buffer).unwrap();
+    ///
+    /// assert_eq!(1, result);
+    /// assert_eq!(16, buffer.len());
+    /// assert_eq!(true, hash_queue.is_empty());
+    /// ```
+    pub fn drain_to_writer<W: Write>(&mut self, mut writer: W) -> Result<usize, HashQueueError> {
+        self.invalidate_snapshot()?;
+        let mut drained = 0;
+        while let Some((key, val)) = self.tree.first()? {
+            let data: T = self.decode(val.deref())?;
+            writer.write_all(&(val.len() as u64).to_be_bytes())?;
+            writer.write_all(&val)?;
+
+            self.tree.remove(&key)?;
+            self.forget_delay(&key)?;
+            if !self.release(&data) {
+                return Err(self.desync("drain_to_writer"));
+            }
+            telemetry::record_pop(&self.name, self.item_count());
+            drained += 1;
+        }
+        writer.flush()?;
+        self.autoflush()?;
+        Ok(drained)
+    }
+
+    ///Name: load_from_reader
+    ///
+    /// Desc: This function reads frames written by `drain_to_writer` from `reader`, and pushes each element onto the back of the queue, returning how many were newly added.
+    /// Elements that are already in the queue are skipped, just like `push_back`.
+    ///
+    /// Additional notes: The stream must end on a frame boundary. If it ends part way through a frame, this function will return `HashQueueError::TruncatedFrame`,
+    ///                   with the (zero based) number of the incomplete frame. Every complete frame before it will already have been pushed.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut source = HashQueue::open_temp("test").unwrap();
+    /// let mut dest: HashQueue<u64> = HashQueue::open_temp("test").unwrap();
+    ///
+    /// source.push_back(1u64).unwrap();
+    ///
+    /// let mut buffer = Vec::new();
+    /// source.drain_to_writer(&mut buffer).unwrap();
+    /// let result = dest.load_from_reader(buffer.as_slice()).unwrap();
+    ///
+    /// assert_eq!(1, result);
+    /// assert_eq!(Some(1), dest.front().unwrap());
+    /// ```
+    pub fn load_from_reader<R: Read>(&mut self, mut reader: R) -> Result<usize, HashQueueError> {
+        let mut loaded = 0;
+        let mut frame = 0;
+        loop {
+            let mut length = [0u8; 8];
+            match read_until_full(&mut reader, &mut length)? {
+                0 => break,
+                8 => {}
+                _ => return Err(HashQueueError::TruncatedFrame { frame }),
+            }
+            //Read through take rather than allocating the whole frame up front, so a corrupt length can't allocate more than the stream holds.
+            let length = u64::from_be_bytes(length);
+            let mut val = Vec::new();
+            (&mut reader).take(length).read_to_end(&mut val)?;
+            if val.len() as u64 != length {
+                return Err(HashQueueError::TruncatedFrame { frame });
+            }
+            if self.push_back(self.decode(&val)?)? {
+                loaded += 1;
+            }
+            frame += 1;
+        }
+        Ok(loaded)
+    }
+
+    ///Panics if the indices in the tree, in the order sled iterates them, aren't strictly increasing. The queue's ordering relies on sled's
+    /// key order matching index order, so tests call this to catch any change to the indexing scheme that breaks it.
+    #[cfg(test)]
+    pub(crate) fn assert_keys_sorted(&self) {
+        let mut previous: Option<i64> = None;
+        for key in self.tree.iter().keys() {
+            let index = index_from_key(&key.expect("assert_keys_sorted: failure to read key"));
+            if let Some(previous) = previous {
+                assert!(previous < index, "assert_keys_sorted: index {} came after index {}", index, previous);
+            }
+            previous = Some(index);
+        }
+    }
+
+    ///Name: requeue_delayed
+    ///
+    /// Desc: This function pushes an element onto the back of the queue, but hides it from `front`, `front_cached` and `pop_front` until `delay` has elapsed.
+    /// While it is hidden, those functions skip over it to the next element that is visible. This is intended for retrying a failed element after a backoff.
+    /// If the element is rejected as a duplicate (see `push_back`), the queue is left unchanged.
+    ///
+    /// Additional notes: The time each delayed element becomes visible is stored in a sidecar tree, so it survives a restart. Other functions, like `back`, `pop_back`
+    ///                   and `is_empty`, still see delayed elements. This requires the queue to have opened its own database, if the queue was created with `from_tree`
+    ///                   this function will return `HashQueueError::Unsupported`.
+    ///
+    /// Usage:
+    ///```
+    /// use std::time::Duration;
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.requeue_delayed(1, Duration::from_secs(60)).unwrap();
+    /// hash_queue.push_back(2).unwrap();
+    ///
+    /// assert_eq!(Some(2), hash_queue.pop_front().unwrap());
+    /// assert_eq!(None, hash_queue.pop_front().unwrap());
+    /// ```
+    pub fn requeue_delayed(&mut self, value: T, delay: Duration) -> Result<(), HashQueueError> {
+        let visible_at = unix_millis().saturating_add(delay.as_millis() as u64);
+        self.push_back_stamped(value, Self::open_delays, visible_at)?;
+        Ok(())
+    }
+
+    ///Name: push_back_with_ttl
+    ///
+    /// Desc: This function pushes an element onto the back of the queue that expires once `ttl` has elapsed. An expired element is skipped by `front` and `front_cached`,
+    /// and removed by `pop_front` when it reaches it, as if it had never been pushed. `purge_expired` removes every expired element straight away.
+    /// Like `push_back`, this returns ```Ok(true)``` if the element was added, and ```Ok(false)``` if it was rejected as a duplicate.
+    ///
+    /// Additional notes: The time each element expires is stored in a sidecar tree, so it survives a restart. Until an expired element is removed, other functions,
+    ///                   like `back`, `pop_back`, `len` and `contains`, still see it. This requires the queue to have opened its own database, if the queue was created
+    ///                   with `from_tree` this function will return `HashQueueError::Unsupported`.
+    ///
+    /// Usage:
+    ///```
+    /// use std::time::Duration;
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back_with_ttl(1, Duration::ZERO).unwrap();
+    /// hash_queue.push_back(2).unwrap();
+    ///
+    /// assert_eq!(Some(2), hash_queue.pop_front().unwrap());
+    /// assert_eq!(true, hash_queue.is_empty());
+    /// ```
+    pub fn push_back_with_ttl(&mut self, value: T, ttl: Duration) -> Result<bool, HashQueueError> {
+        let expires_at = unix_millis().saturating_add(ttl.as_millis() as u64);
+        self.push_back_stamped(value, Self::open_expiries, expires_at)
+    }
+
+    ///Name: purge_expired
+    ///
+    /// Desc: This function removes every element pushed with `push_back_with_ttl` that has expired, wherever it is in the queue, and returns how many were removed.
+    /// `pop_front` only removes expired elements as it reaches them, so this reclaims the space they take up, and makes `len` and `contains` accurate again.
+    ///
+    /// Usage:
+    ///```
+    /// use std::time::Duration;
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    /// hash_queue.push_back_with_ttl(2, Duration::ZERO).unwrap();
+    ///
+    /// assert_eq!(1, hash_queue.purge_expired().unwrap());
+    /// assert_eq!(1, hash_queue.len());
+    /// ```
+    pub fn purge_expired(&mut self) -> Result<usize, HashQueueError> {
+        let Some(expiries) = self.expiries.clone() else {
+            return Ok(0);
+        };
+        let now = unix_millis();
+        let mut removed = 0;
+        for entry in expiries.iter() {
+            let (key, expires_at) = entry?;
+            if u64::from_be_bytes(expires_at.as_ref().try_into().expect("purge_expired: couldn't convert time to bytes")) > now {
+                continue;
+            }
+            match self.tree.get(&key)? {
+                Some(val) => {
+                    self.invalidate_snapshot()?;
+                    if self.remove_expired(&key, &val)? {
+                        removed += 1;
+                    }
+                }
+                //The element was already removed, so only the expiry was left behind.
+                None => {
+                    expiries.remove(&key)?;
+                }
+            }
+        }
+        self.autoflush()?;
+        Ok(removed)
+    }
+
+    //This is an internal function that pushes an element onto the back of the queue, and stores `stamp` for it in the sidecar tree `open_sidecar` opens, in a single transaction.
+    //It returns false without opening the sidecar tree if the element is rejected as a duplicate.
+    fn push_back_stamped(&mut self, value: T, open_sidecar: fn(&mut Self) -> Result<Tree, HashQueueError>, stamp: u64) -> Result<bool, HashQueueError> {
+        self.check_push(&value)?;
+        let rejected = match self.options.dedup {
+            DedupMode::Global => self.set.contains(&value),
+            DedupMode::AdjacentOnly => self.back()?.as_ref() == Some(&value),
+            DedupMode::Off => false,
+        };
+        if rejected {
+            self.rejected_duplicates += 1;
+            return Ok(false);
+        }
+        let sidecar = open_sidecar(self)?;
+        let val = self.encode(&value)?;
+        let stamp = stamp.to_be_bytes();
+
+        self.invalidate_snapshot()?;
+        loop {
+            let key = key_from_index(self.back_index()?);
+            // Another handle on the same tree may have taken the key since back_index read it, in which case the transaction aborts and the key is recalculated.
+            let result: TransactionResult<(), ()> = (&self.tree, &sidecar).transaction(|(tree, sidecar)| {
+                if tree.get(key)?.is_some() {
+                    return Err(ConflictableTransactionError::Abort(()));
+                }
+                sidecar.insert(&key, &stamp)?;
+                tree.insert(&key, val.clone())?;
+                Ok(())
+            });
+            #[cfg(test)]
+            {
+                self.forced_back_index = None;
+            }
+            match result {
+                Ok(()) => break,
+                Err(TransactionError::Storage(error)) => return Err(error.into()),
+                Err(TransactionError::Abort(())) => continue,
+            }
+        }
+        self.track(value);
+        self.autoflush()?;
+        telemetry::record_push(&self.name, self.item_count());
+        Ok(true)
+    }
+
+    //This is an internal function that returns the delays tree, opening it first if no element has been delayed yet.
+    fn open_delays(&mut self) -> Result<Tree, HashQueueError> {
+        if let Some(delays) = &self.delays {
+            return Ok(delays.clone());
+        }
+        let Some(db) = &self.db else {
+            return Err(HashQueueError::Unsupported {
+                message: "delaying elements needs the queue to have opened its own database".to_string(),
+            });
+        };
+        let delays = db.open_tree([self.tree.name().as_ref(), DELAYS_TREE_SUFFIX].concat())?;
+        self.delays = Some(delays.clone());
+        Ok(delays)
+    }
+
+    //This is an internal function that returns the expiries tree, opening it first if no element has been pushed with a TTL yet.
+    fn open_expiries(&mut self) -> Result<Tree, HashQueueError> {
+        if let Some(expiries) = &self.expiries {
+            return Ok(expiries.clone());
+        }
+        let Some(db) = &self.db else {
+            return Err(HashQueueError::Unsupported {
+                message: "pushing elements with a TTL needs the queue to have opened its own database".to_string(),
+            });
+        };
+        let expiries = db.open_tree([self.tree.name().as_ref(), EXPIRIES_TREE_SUFFIX].concat())?;
+        self.expiries = Some(expiries.clone());
+        Ok(expiries)
+    }
+
+    ///Name: health_check
+    ///
+    /// Desc: This function runs a quick check of the queue's health, suitable for a liveness probe. It checks that the tree can be read, that the tree holds as many elements
+    /// as the set tracks, and whether the set and tree have been found to be out of sync. It doesn't deserialize any elements.
+    /// A failure to read the tree is reported through `reachable`, rather than as a HashQueueError.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    ///
+    /// let result = hash_queue.health_check().unwrap();
+    ///
+    /// assert_eq!(true, result.reachable);
+    /// ```
+    pub fn health_check(&self) -> Result<HealthStatus, HashQueueError> {
+        let reachable = self.tree.first().is_ok();
+        let consistent = reachable && self.tree.len() == self.item_count();
+        Ok(HealthStatus {
+            reachable,
+            consistent,
+            poisoned: self.poisoned.load(Ordering::Relaxed),
+        })
+    }
+
+    ///Name: iter_raw
+    ///
+    /// Desc: This function iterates over the queue from front to back, yielding the index each element is stored at and its serialized bytes, without deserializing anything.
+    /// This is intended for replicating or backing up a queue byte for byte. The queue is not modified.
+    /// When the queue is ordered by priority, each element's priority is yielded in place of its index.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1u64).unwrap();
+    ///
+    /// let result = hash_queue.iter_raw().next().unwrap().unwrap();
+    ///
+    /// assert_eq!(0, result.0);
+    /// assert_eq!(bincode::serialize(&1u64).unwrap(), result.1.to_vec());
+    /// ```
+    pub fn iter_raw(&self) -> impl Iterator<Item = Result<(i64, IVec), HashQueueError>> + '_ {
+        self.tree.iter().map(|entry| {
+            let (key, val) = entry?;
+            if key.len() != self.options.key_len() {
+                return Err(HashQueueError::InvalidKey { key: key.to_vec() });
+            }
+            Ok((index_from_key(&key), val))
+        })
+    }
+
+    ///Name: apply_raw
+    ///
+    /// Desc: This function writes index and serialized bytes pairs, like the ones yielded by `iter_raw`, directly into the tree in a single batch, then rebuilds the set from the tree.
+    /// This restores a queue byte for byte without serializing anything again. Pairs at an index that's already in use overwrite the element stored there.
+    /// A queue ordered by priority has no indices to write to, so this returns `HashQueueError::Unsupported` for one.
+    ///
+    /// Additional notes: Every value is checked to deserialize before anything is written, so if one doesn't, this function will return a `HashQueueError` and the queue is unchanged.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue: HashQueue<u64> = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.apply_raw(vec![(0, bincode::serialize(&1u64).unwrap())].into_iter()).unwrap();
+    ///
+    /// assert_eq!(Some(1), hash_queue.front().unwrap());
+    /// ```
+    pub fn apply_raw(&mut self, pairs: impl Iterator<Item = (i64, Vec<u8>)>) -> Result<(), HashQueueError> {
+        self.check_fifo("apply_raw")?;
+        let mut batch = Batch::default();
+        let mut keys = Vec::new();
+        for (index, val) in pairs {
+            self.decode(&val)?;
+            batch.insert(&key_from_index(index), val);
+            keys.push(key_from_index(index));
+        }
+
+        self.invalidate_snapshot()?;
+        self.tree.apply_batch(batch)?;
+        for key in &keys {
+            self.forget_delay(key)?;
+        }
+        self.autoflush()?;
+        let (set, duplicates) = Self::collect_set(&self.tree, &self.options)?;
+        self.set = set;
+        self.duplicates = duplicates;
+        Ok(())
+    }
+
+    ///Name: rotate_n
+    ///
+    /// Desc: This function moves the first `n` elements from the front of the queue to the back, keeping their relative order, and returns them.
+    /// The elements are moved in a single batch, so the queue is never seen with only some of them moved. If `n` is `0`, or at least the length of the queue,
+    /// the order of the queue doesn't change, but the elements that would have been moved are still returned.
+    ///
+    /// Additional notes: Delayed elements are moved like any other element, and stay delayed until the same time. Elements pushed with a TTL keep it too.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    /// hash_queue.push_back(2).unwrap();
+    /// hash_queue.push_back(3).unwrap();
+    ///
+    /// let result = hash_queue.rotate_n(1).unwrap();
+    ///
+    /// assert_eq!(vec![1], result);
+    /// assert_eq!(Some(2), hash_queue.front().unwrap());
+    /// assert_eq!(Some(1), hash_queue.back().unwrap());
+    /// ```
+    pub fn rotate_n(&mut self, n: usize) -> Result<Vec<T>, HashQueueError> {
+        let mut entries: Vec<(IVec, IVec)> = Vec::new();
+        let mut iter = self.tree.iter();
+        for entry in iter.by_ref().take(n) {
+            entries.push(entry?);
+        }
+        let items = entries
+            .iter()
+            .map(|(_key, val)| self.decode(val.deref()))
+            .collect::<Result<Vec<T>, _>>()?;
+        if entries.is_empty() || iter.next().is_none() {
+            return Ok(items);
+        }
+
+        let mut index = self.back_index()?;
+        let mut batch = Batch::default();
+        let mut delay_batch = Batch::default();
+        let mut expiry_batch = Batch::default();
+        for (key, val) in &entries {
+            let new_key = key_from_index(index);
+            batch.remove(key);
+            batch.insert(&new_key, val);
+            for (sidecar, sidecar_batch) in [(&self.delays, &mut delay_batch), (&self.expiries, &mut expiry_batch)] {
+                if let Some(stamp) = sidecar.as_ref().map(|sidecar| sidecar.get(key)).transpose()?.flatten() {
+                    sidecar_batch.remove(key);
+                    sidecar_batch.insert(&new_key, stamp);
+                }
+            }
+            index = index.checked_add(1).ok_or(HashQueueError::IndexOverflow { index })?;
+        }
+
+        if self.delays.is_some() || self.expiries.is_some() {
+            let delays = self.open_delays()?;
+            let expiries = self.open_expiries()?;
+            let result: TransactionResult<(), ()> = (&self.tree, &delays, &expiries).transaction(|(tree, delays, expiries)| {
+                tree.apply_batch(&batch)?;
+                delays.apply_batch(&delay_batch)?;
+                expiries.apply_batch(&expiry_batch)?;
+                Ok(())
+            });
+            match result {
+                Ok(()) => {}
+                Err(TransactionError::Storage(error)) => return Err(error.into()),
+                Err(TransactionError::Abort(())) => unreachable!("rotate_n: the transaction never aborts"),
+            }
+        } else {
+            self.tree.apply_batch(batch)?;
+        }
+        #[cfg(test)]
+        {
+            self.forced_back_index = None;
+        }
+        self.autoflush()?;
+        Ok(items)
+    }
+
+    ///Name: len_approx
+    ///
+    /// Desc: This function estimates how many elements are in the tree from the indices at the front and back of it, without scanning the tree or relying on the set.
+    /// Only the first and last keys are read, so this is cheap no matter how long the queue is. This is intended for checking the size of a tree shared with other handles,
+    /// where this queue's own `len` may be out of date.
+    ///
+    /// Additional notes: The estimate is exact while the queue is contiguous (see `is_contiguous`). Each hole left by removing an element from the middle of the queue
+    ///                   makes the estimate one too large, so it is never smaller than the real length. The tree doesn't keep a count of its holes, so they can't be subtracted.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    /// hash_queue.push_back(2).unwrap();
+    ///
+    /// assert_eq!(2, hash_queue.len_approx().unwrap());
+    /// ```
+    pub fn len_approx(&self) -> Result<usize, HashQueueError> {
+        let (Some(first), Some(last)) = (self.first_key()?, self.last_key()?) else {
+            return Ok(0);
+        };
+        let span = last as i128 - first as i128 + 1;
+        Ok(usize::try_from(span).unwrap_or(usize::MAX))
+    }
+
+    ///Name: clear_if
+    ///
+    /// Desc: This function calls `pred` with the queue as it currently is, and only clears the queue (see `clear`) if `pred` returns true.
+    /// It returns whether the queue was cleared. This is intended for resetting a queue only when it is safe to, for example only if every element has expired.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    ///
+    /// let result = hash_queue.clear_if(|queue| queue.front().unwrap() == Some(1)).unwrap();
+    ///
+    /// assert_eq!(true, result);
+    /// assert_eq!(true, hash_queue.is_empty());
+    /// ```
+    pub fn clear_if<F: FnOnce(&HashQueue<T>) -> bool>(&mut self, pred: F) -> Result<bool, HashQueueError> {
+        if !pred(self) {
+            return Ok(false);
+        }
+        self.clear()?;
+        Ok(true)
+    }
+
+    ///Name: first_key
+    ///
+    /// Desc: This function returns the index the front element of the queue is stored at, if the queue isn't empty. The element itself isn't deserialized,
+    /// so this is cheaper than `front` when only the position is needed, for example for monitoring. Delayed elements aren't skipped.
+    /// When the queue is ordered by priority, this returns the front element's priority.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    /// hash_queue.push_back(2).unwrap();
+    ///
+    /// assert_eq!(Some(0), hash_queue.first_key().unwrap());
+    /// ```
+    pub fn first_key(&self) -> Result<Option<i64>, HashQueueError> {
+        match self.tree.first()? {
+            Some((key, _val)) if key.len() != self.options.key_len() => Err(HashQueueError::InvalidKey { key: key.to_vec() }),
+            Some((key, _val)) => Ok(Some(index_from_key(&key))),
+            None => Ok(None),
+        }
+    }
+
+    ///Name: last_key
+    ///
+    /// Desc: This function returns the index the back element of the queue is stored at, if the queue isn't empty. Like `first_key`, the element isn't deserialized.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    /// hash_queue.push_back(2).unwrap();
+    ///
+    /// assert_eq!(Some(1), hash_queue.last_key().unwrap());
+    /// ```
+    pub fn last_key(&self) -> Result<Option<i64>, HashQueueError> {
+        match self.tree.last()? {
+            Some((key, _val)) if key.len() != self.options.key_len() => Err(HashQueueError::InvalidKey { key: key.to_vec() }),
+            Some((key, _val)) => Ok(Some(index_from_key(&key))),
+            None => Ok(None),
+        }
+    }
+
+    ///Name: push_back_force
+    ///
+    /// Desc: This function pushes an element to the back of the queue even if it is already present, and returns the index it was stored at.
+    /// This is intended for re-delivering an element that is still queued.
+    ///
+    /// Additional notes: If the element was already present, the tree will hold a second entry for it. The queue counts each extra copy, so popping or removing
+    ///                   one of the entries leaves the element tracked until every copy is gone. Like `push_back`, this will return `HashQueueError::ElementTooLarge`
+    ///                   if the element is larger than `max_element_bytes`, and `HashQueueError::CapacityExceeded` if the queue is full.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    ///
+    /// let result = hash_queue.push_back_force(1).unwrap();
+    ///
+    /// assert_eq!(1, result);
+    /// assert_eq!(Some(1), hash_queue.pop_front().unwrap());
+    /// assert_eq!(Some(1), hash_queue.pop_front().unwrap());
+    /// ```
+    pub fn push_back_force(&mut self, value: T) -> Result<i64, HashQueueError> {
+        self.check_push(&value)?;
+        let val = self.encode(&value)?;
+        self.invalidate_snapshot()?;
+        let index = self.insert_free(&val, Self::back_index)?;
+        #[cfg(test)]
+        {
+            self.forced_back_index = None;
+        }
+        self.track(value);
+        self.autoflush()?;
+        telemetry::record_push(&self.name, self.item_count());
+        Ok(index)
+    }
+
+    ///Name: checkpoint
+    ///
+    /// Desc: This function records a checkpoint at the back of the queue, marking every element currently queued as committed, and returns the checkpoint's id.
+    /// The queue is flushed first, so the checkpoint never covers an element that isn't on disk. Passing the id to `rewind_to` later removes every element pushed after the checkpoint.
+    ///
+    /// Additional notes: Checkpoints are stored in a sidecar tree, so they survive a restart. This requires the queue to have opened its own database, if the queue was created
+    ///                   with `from_tree` this function will return `HashQueueError::Unsupported`. A checkpoint only remembers an index, so if the queue is emptied after the checkpoint
+    ///                   is taken, indices start over from `0` and elements pushed afterwards can't be told apart from the ones before it.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    ///
+    /// let checkpoint = hash_queue.checkpoint().unwrap();
+    ///
+    /// hash_queue.push_back(2).unwrap();
+    /// hash_queue.rewind_to(checkpoint).unwrap();
+    ///
+    /// assert_eq!(Some(1), hash_queue.back().unwrap());
+    /// ```
+    pub fn checkpoint(&mut self) -> Result<u64, HashQueueError> {
+        let checkpoints = self.open_checkpoints()?;
+        let id = self.db.as_ref().expect("checkpoint: the checkpoints tree is only opened from the queue's own database").generate_id()?;
+        self.flush_pending()?;
+        checkpoints.insert(id.to_be_bytes(), &key_from_index(self.back_index()?))?;
+        checkpoints.flush()?;
+        Ok(id)
+    }
+
+    ///Name: rewind_to
+    ///
+    /// Desc: This function rolls the queue back to the checkpoint with the given id (see `checkpoint`), removing every element pushed after it, and returns how many were removed.
+    /// Elements from before the checkpoint that have been popped since aren't restored, and elements pushed with `push_front` since aren't removed.
+    /// Checkpoints taken after this one are discarded, since the elements they cover are gone.
+    ///
+    /// Additional notes: If there's no checkpoint with the given id, this function will return `HashQueueError::UnknownCheckpoint`. The elements are removed in a single transaction,
+    ///                   so if one of them can't be deserialized, the error is returned and nothing is removed.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// let checkpoint = hash_queue.checkpoint().unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    ///
+    /// let result = hash_queue.rewind_to(checkpoint).unwrap();
+    ///
+    /// assert_eq!(1, result);
+    /// assert_eq!(true, hash_queue.is_empty());
+    /// ```
+    pub fn rewind_to(&mut self, id: u64) -> Result<usize, HashQueueError> {
+        let checkpoints = self.open_checkpoints()?;
+        let Some(boundary) = checkpoints.get(id.to_be_bytes())? else {
+            return Err(HashQueueError::UnknownCheckpoint { id });
+        };
+
+        //Every element is deserialized before anything is removed, so if one can't be, the queue and its checkpoints are left as they were.
+        let mut items = Vec::new();
+        let mut batch = Batch::default();
+        for entry in self.tree.range(boundary..) {
+            let (key, val) = entry?;
+            items.push(self.decode(val.deref())?);
+            batch.remove(key);
+        }
+        let mut checkpoint_batch = Batch::default();
+        for later in checkpoints.range(id.saturating_add(1).to_be_bytes()..).keys() {
+            checkpoint_batch.remove(later?);
+        }
+
+        //The elements, their delays and expiries, and the later checkpoints are removed together, so a failure can't leave a checkpoint pointing at elements that are gone.
+        self.invalidate_snapshot()?;
+        let mut trees = vec![self.tree.clone(), checkpoints.clone()];
+        let mut batches = vec![&batch, &checkpoint_batch];
+        for sidecar in self.delays.iter().chain(&self.expiries) {
+            trees.push(sidecar.clone());
+            batches.push(&batch);
+        }
+        let result: TransactionResult<(), ()> = trees.as_slice().transaction(|trees| {
+            for (tree, batch) in trees.iter().zip(&batches) {
+                tree.apply_batch(batch)?;
+            }
+            Ok(())
+        });
+        match result {
+            Ok(()) => {}
+            Err(TransactionError::Storage(error)) => return Err(error.into()),
+            Err(TransactionError::Abort(())) => unreachable!("rewind_to: the transaction never aborts"),
+        }
+        for item in &items {
+            if !self.release(item) {
+                return Err(self.desync("rewind_to"));
+            }
+        }
+        self.autoflush()?;
+        checkpoints.flush()?;
+        telemetry::record_evictions(&self.name, items.len(), self.item_count());
+        Ok(items.len())
+    }
+
+    //This is an internal function that opens the tree holding the queue's checkpoints.
+    fn open_checkpoints(&self) -> Result<Tree, HashQueueError> {
+        let Some(db) = &self.db else {
+            return Err(HashQueueError::Unsupported {
+                message: "checkpoints need the queue to have opened its own database".to_string(),
+            });
+        };
+        Ok(db.open_tree([self.tree.name().as_ref(), CHECKPOINTS_TREE_SUFFIX].concat())?)
+    }
+
+    ///Name: reconcile
+    ///
+    /// Desc: This function removes any value from the set that isn't stored anywhere in the tree, and returns how many were removed.
+    /// This fixes a set that has got ahead of the tree, for example after a write to the tree failed, without rebuilding the whole set.
+    /// Elements that are in the tree but missing from the set aren't added back.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    ///
+    /// let result = hash_queue.reconcile().unwrap();
+    ///
+    /// assert_eq!(0, result);
+    /// ```
+    pub fn reconcile(&mut self) -> Result<usize, HashQueueError> {
+        let (stored, _duplicates) = Self::collect_set(&self.tree, &self.options)?;
+        let orphans: Vec<T> = self.set.iter().filter(|value| !stored.contains(*value)).cloned().collect();
+        if orphans.is_empty() {
+            return Ok(0);
+        }
+        self.invalidate_snapshot()?;
+        for orphan in &orphans {
+            self.set.remove(orphan);
+            self.duplicates.remove(orphan);
+        }
+        Ok(orphans.len())
+    }
+
+    ///Name: stable_iter
+    ///
+    /// Desc: This function returns an iterator over the elements of the queue from front to back, as of when it was called. The keys in the tree are captured up front,
+    /// and each element is read as the iterator reaches it. Elements removed since the call, by this or any other handle on the tree, are skipped, and elements pushed since the call are never yielded.
+    ///
+    /// Additional notes: Only the keys are captured, so this holds one key per element in memory. If an element is removed and another is pushed at the same index before
+    ///                   the iterator reaches it, which can happen after a `pop_back`, the iterator yields the new element.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    /// hash_queue.push_back(2).unwrap();
+    ///
+    /// let iter = hash_queue.stable_iter().unwrap();
+    ///
+    /// let result = iter.collect::<Result<Vec<i32>, _>>().unwrap();
+    ///
+    /// assert_eq!(vec![1, 2], result);
+    /// ```
+    pub fn stable_iter(&self) -> Result<impl Iterator<Item = Result<T, HashQueueError>> + '_, HashQueueError> {
+        let keys = self.tree.iter().keys().collect::<Result<Vec<IVec>, Error>>()?;
+        Ok(keys.into_iter().filter_map(|key| match self.tree.get(&key) {
+            Ok(Some(val)) => Some(self.decode(&val)),
+            Ok(None) => None,
+            Err(error) => Some(Err(error.into())),
+        }))
+    }
+
+    ///Name: next_after
+    ///
+    /// Desc: This function returns the element directly behind `value` in the queue, that is the element that will be popped from the front right after it.
+    /// If `value` is at the back of the queue, or isn't in the queue at all, this will return `Ok(None)`. The queue is not modified.
+    ///
+    /// Additional notes: If the queue holds more than one copy of `value`, the copy closest to the front is used.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    /// hash_queue.push_back(2).unwrap();
+    ///
+    /// assert_eq!(Some(2), hash_queue.next_after(&1).unwrap());
+    /// assert_eq!(None, hash_queue.next_after(&2).unwrap());
+    /// ```
+    pub fn next_after(&self, value: &T) -> Result<Option<T>, HashQueueError> {
+        let Some(key) = self.find_key(value, "next_after")? else {
+            return Ok(None);
+        };
+        match self.tree.range((Bound::Excluded(key), Bound::Unbounded)).next() {
+            Some(entry) => Ok(Some(self.decode(entry?.1.deref())?)),
+            None => Ok(None),
+        }
+    }
+
+    ///Name: prev_before
+    ///
+    /// Desc: This function returns the element directly in front of `value` in the queue, that is the element that will be popped from the front right before it.
+    /// If `value` is at the front of the queue, or isn't in the queue at all, this will return `Ok(None)`. The queue is not modified.
+    ///
+    /// Additional notes: If the queue holds more than one copy of `value`, the copy closest to the front is used.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    /// hash_queue.push_back(2).unwrap();
+    ///
+    /// assert_eq!(Some(1), hash_queue.prev_before(&2).unwrap());
+    /// assert_eq!(None, hash_queue.prev_before(&1).unwrap());
+    /// ```
+    pub fn prev_before(&self, value: &T) -> Result<Option<T>, HashQueueError> {
+        let Some(key) = self.find_key(value, "prev_before")? else {
+            return Ok(None);
+        };
+        match self.tree.range(..key).next_back() {
+            Some(entry) => Ok(Some(self.decode(entry?.1.deref())?)),
+            None => Ok(None),
+        }
+    }
+
+    //This is an internal function that returns the key of the first entry in the tree holding `value`, or None if the set doesn't track it.
+    fn find_key(&self, value: &T, caller: &str) -> Result<Option<IVec>, HashQueueError> {
+        Ok(self.find_entry(value, caller)?.map(|(key, _val)| key))
+    }
+
+    //This is an internal function that returns the key and stored bytes of the first entry in the tree holding `value`, or None if the set doesn't track it.
+    fn find_entry(&self, value: &T, caller: &str) -> Result<Option<(IVec, IVec)>, HashQueueError> {
+        if !self.set.contains(value) {
+            return Ok(None);
+        }
+        for entry in self.tree.iter() {
+            let (key, val) = entry?;
+            let item: T = self.decode(val.deref())?;
+            if &item == value {
+                return Ok(Some((key, val)));
+            }
+        }
+        Err(self.desync(caller))
+    }
+
+    ///Name: pop_front_into_tree
+    ///
+    /// Desc: This function pops the front element of the queue like `pop_front`, and writes it to `dest` in the same sled transaction, so the element is either
+    /// in this queue or in `dest`, never both and never neither. The element is written to `dest` under the same key it had in the queue, so if sled retries the
+    /// transaction it is still only written once. This is intended for handing elements off to another tree, like a log of processed elements, exactly once.
+    ///
+    /// Additional notes: `dest` must belong to the same database as the queue, since sled can't run a transaction across databases. A front element that can't be
+    ///                   deserialized will return a `HashQueueError`, and is left at the front of the queue.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let db = sled::Config::new().temporary(true).open().unwrap();
+    /// let dest = db.open_tree("processed").unwrap();
+    /// let mut hash_queue = HashQueue::from_tree(db.open_tree("test").unwrap()).unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    ///
+    /// let result = hash_queue.pop_front_into_tree(&dest).unwrap();
+    ///
+    /// assert_eq!(Some(1), result);
+    /// assert_eq!(1, dest.len());
+    /// ```
+    pub fn pop_front_into_tree(&mut self, dest: &Tree) -> Result<Option<T>, HashQueueError> {
+        self.invalidate_snapshot()?;
+        loop {
+            let Some((key, val)) = self.next_visible()? else {
+                return Ok(None);
+            };
+            let data: T = self.decode(val.deref())?;
+            let result: TransactionResult<(), ()> = (&self.tree, dest).transaction(|(tree, dest)| {
+                if tree.get(&key)?.as_ref() != Some(&val) {
+                    return Err(ConflictableTransactionError::Abort(()));
+                }
+                tree.remove(&key)?;
+                dest.insert(&key, &val)?;
+                Ok(())
+            });
+            match result {
+                Ok(()) => {}
+                Err(TransactionError::Storage(error)) => return Err(error.into()),
+                //Another handle on the tree removed or replaced the entry after it was read, so try again with the new front.
+                Err(TransactionError::Abort(())) => continue,
+            }
+            self.forget_delay(&key)?;
+            if !self.release(&data) {
+                return Err(self.desync("pop_front_into_tree"));
+            }
+            self.autoflush()?;
+            dest.flush()?;
+            telemetry::record_pop(&self.name, self.item_count());
+            return Ok(Some(data));
+        }
+    }
+
+    ///Name: iter
+    ///
+    /// Desc: This function iterates over the queue from front to back, deserializing each element as the iterator reaches it. The queue is not modified.
+    /// Delayed elements are included. Each element is yielded as a `Result`, since an element that can't be deserialized shouldn't stop the rest from being read.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    /// hash_queue.push_back(2).unwrap();
+    ///
+    /// let result = hash_queue.iter().collect::<Result<Vec<i32>, _>>().unwrap();
+    ///
+    /// assert_eq!(vec![1, 2], result);
+    /// ```
+    pub fn iter(&self) -> impl Iterator<Item = Result<T, HashQueueError>> + '_ {
+        self.tree.iter().values().map(|val| self.decode(&val?))
+    }
+
+    ///Name: drain
+    ///
+    /// Desc: This function removes every element from the queue, and returns them in order from front to back. The elements are removed from the tree
+    /// in a single batch, which is flushed once at the end, so another handle on the tree never sees only some of them removed.
+    /// Elements pushed by another handle while this runs aren't removed.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    /// hash_queue.push_back(2).unwrap();
+    ///
+    /// let result = hash_queue.drain().unwrap();
+    ///
+    /// assert_eq!(vec![1, 2], result);
+    /// assert_eq!(true, hash_queue.is_empty());
+    /// ```
+    pub fn drain(&mut self) -> Result<Vec<T>, HashQueueError> {
+        let mut batch = Batch::default();
+        let mut keys = Vec::new();
+        let mut items = Vec::new();
+        for entry in self.tree.iter() {
+            let (key, val) = entry?;
+            items.push(self.decode(val.deref())?);
+            batch.remove(&key);
+            keys.push(key);
+        }
+
+        self.invalidate_snapshot()?;
+        self.tree.apply_batch(batch)?;
+        for key in &keys {
+            self.forget_delay(key)?;
+        }
+        for item in &items {
+            if !self.release(item) {
+                return Err(self.desync("drain"));
+            }
+            telemetry::record_pop(&self.name, self.item_count());
+        }
+        self.autoflush()?;
+        Ok(items)
+    }
+
+    ///Name: pop_front_n
+    ///
+    /// Desc: This function pops up to `n` elements from the front of the queue, and returns them in order from front to back. If the queue runs out first,
+    /// fewer than `n` elements are returned, and an empty queue returns an empty `Vec`. Each element is popped like `pop_front` does, but the tree is only flushed
+    /// once at the end, rather than once per element.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    /// hash_queue.push_back(2).unwrap();
+    /// hash_queue.push_back(3).unwrap();
+    ///
+    /// assert_eq!(vec![1, 2], hash_queue.pop_front_n(2).unwrap());
+    /// assert_eq!(vec![3], hash_queue.pop_front_n(2).unwrap());
+    /// ```
+    pub fn pop_front_n(&mut self, n: usize) -> Result<Vec<T>, HashQueueError> {
+        self.invalidate_snapshot()?;
+        let mut items = Vec::new();
+        while items.len() < n {
+            let Some((_val, data)) = self.pop_first_visible()? else {
+                break;
+            };
+            if !self.release(&data) {
+                return Err(self.desync("pop_front_n"));
+            }
+            telemetry::record_pop(&self.name, self.item_count());
+            items.push(data);
+        }
+        self.autoflush()?;
+        Ok(items)
+    }
+
+    ///Name: peek_nth
+    ///
+    /// Desc: This function returns the element `n` places from the front of the queue, without removing it, so `peek_nth(0)` is the front of the queue.
+    /// If the queue holds `n` or fewer elements, this will return `Ok(None)`. Delayed elements are counted. The queue is not modified.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    /// hash_queue.push_back(2).unwrap();
+    ///
+    /// assert_eq!(Some(2), hash_queue.peek_nth(1).unwrap());
+    /// assert_eq!(None, hash_queue.peek_nth(2).unwrap());
+    /// ```
+    pub fn peek_nth(&self, n: usize) -> Result<Option<T>, HashQueueError> {
+        match self.tree.iter().values().nth(n) {
+            Some(val) => Ok(Some(self.decode(val?.deref())?)),
+            None => Ok(None),
+        }
+    }
+
+    ///Name: peek_nth_back
+    ///
+    /// Desc: This function returns the element `n` places from the back of the queue, without removing it, so `peek_nth_back(0)` is the back of the queue.
+    /// If the queue holds `n` or fewer elements, this will return `Ok(None)`. Like `peek_nth`, delayed elements are counted and the queue is not modified.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    /// hash_queue.push_back(2).unwrap();
+    ///
+    /// assert_eq!(Some(1), hash_queue.peek_nth_back(1).unwrap());
+    /// assert_eq!(None, hash_queue.peek_nth_back(2).unwrap());
+    /// ```
+    pub fn peek_nth_back(&self, n: usize) -> Result<Option<T>, HashQueueError> {
+        match self.tree.iter().values().rev().nth(n) {
+            Some(val) => Ok(Some(self.decode(val?.deref())?)),
+            None => Ok(None),
+        }
+    }
+
+    ///Name: remove
+    ///
+    /// Desc: This function removes `value` from wherever it is in the queue, leaving the order of the other elements unchanged. It returns `Ok(true)` if
+    /// the value was removed, and `Ok(false)` if it wasn't in the queue. Since the set doesn't know where a value is stored, this scans the queue from the front.
+    ///
+    /// Additional notes: If the queue holds more than one copy of `value`, only the copy closest to the front is removed.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    /// hash_queue.push_back(2).unwrap();
+    ///
+    /// assert_eq!(true, hash_queue.remove(&1).unwrap());
+    /// assert_eq!(Some(2), hash_queue.front().unwrap());
+    /// ```
+    pub fn remove(&mut self, value: &T) -> Result<bool, HashQueueError> {
+        let Some(key) = self.find_key(value, "remove")? else {
+            return Ok(false);
+        };
+        self.invalidate_snapshot()?;
+        self.tree.remove(&key)?;
+        self.forget_delay(&key)?;
+        if !self.release(value) {
+            return Err(self.desync("remove"));
+        }
+        self.autoflush()?;
+        telemetry::record_evictions(&self.name, 1, self.item_count());
+        Ok(true)
+    }
+
+    ///Name: extend_back
+    ///
+    /// Desc: This function pushes every element of `iter` onto the back of the queue, in order, and returns how many were added. Elements that are already
+    /// in the queue are skipped, just like `push_back`. If a push fails, this returns the HashQueueError, and the elements before it are left in the queue.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// let result = hash_queue.extend_back(vec![1, 2, 2]).unwrap();
+    ///
+    /// assert_eq!(2, result);
+    /// ```
+    pub fn extend_back<I: IntoIterator<Item = T>>(&mut self, iter: I) -> Result<usize, HashQueueError> {
+        let mut added = 0;
+        for item in iter {
+            if self.push_back(item)? {
+                added += 1;
+            }
+        }
+        Ok(added)
+    }
+
+    ///Name: append
+    ///
+    /// Desc: This function moves every element of `other` onto the back of this queue, in order, and returns how many were added. Elements that are already
+    /// in this queue are skipped, just like `push_back`, but are still removed from `other`, so `other` is empty once this returns. This is intended for consolidating sharded queues.
+    ///
+    /// Additional notes: Each element is only removed from `other` once it has been pushed, so if a push fails, this returns the HashQueueError, and the element is left
+    ///                   at the front of `other`. Delayed elements in `other` are moved too, but are visible straight away in this queue. Rather than flushing after
+    ///                   every element, both queues are flushed once at the end, this queue before `other`.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    /// let mut other = HashQueue::open_temp("other").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    /// other.push_back(2).unwrap();
+    ///
+    /// assert_eq!(1, hash_queue.append(&mut other).unwrap());
+    /// assert_eq!(Some(2), hash_queue.back().unwrap());
+    /// assert_eq!(true, other.is_empty());
+    /// ```
+    pub fn append(&mut self, other: &mut HashQueue<T>) -> Result<usize, HashQueueError> {
+        let autoflush = std::mem::replace(&mut self.autoflush, false);
+        let other_autoflush = std::mem::replace(&mut other.autoflush, false);
+        let result = self.append_unflushed(other);
+        self.autoflush = autoflush;
+        other.autoflush = other_autoflush;
+        self.autoflush()?;
+        other.autoflush()?;
+        result
+    }
+
+    //This is an internal function that moves the elements for `append` with autoflush turned off. The elements are popped from `other`, rather than dropped,
+    //so they're reported as pops, not evictions.
+    fn append_unflushed(&mut self, other: &mut HashQueue<T>) -> Result<usize, HashQueueError> {
+        let mut added = 0;
+        other.invalidate_snapshot()?;
+        while let Some(item) = other.peek_nth(0)? {
+            if self.push_back(item)? {
+                added += 1;
+            }
+            if other.remove_end(false, "append")? {
+                telemetry::record_pop(&other.name, other.item_count());
+            }
+        }
+        Ok(added)
+    }
+
+    ///Name: subscribe
+    ///
+    /// Desc: This function returns a `Subscription` that receives an event for every change made to the queue's tree from then on, by this or any other handle on the tree.
+    /// This lets a worker on another thread block until an element is pushed, rather than polling `is_empty`. See `QueueEvent` for what each change is reported as.
+    ///
+    /// Usage:
+    ///```
+    /// use std::time::Duration;
+    /// use set_deque::hash_queue::{HashQueue, QueueEvent};
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// let mut subscription = hash_queue.subscribe();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    ///
+    /// let result = subscription.next_timeout(Duration::from_secs(5)).unwrap().unwrap();
+    ///
+    /// assert_eq!(QueueEvent::Pushed { index: 0, item: 1 }, result);
+    /// ```
+    pub fn subscribe(&self) -> Subscription<T> {
+        Subscription {
+            subscriber: self.tree.watch_prefix(Vec::new()),
+            options: self.options.clone(),
+            item: PhantomData,
+        }
+    }
+
+    ///Name: push_back_batch
+    ///
+    /// Desc: This function pushes every element of `values` onto the back of the queue in a single sled transaction, so either all of them are written or none are,
+    /// even if the process crashes part way through. It returns how many were added. Elements that are already in the queue, or earlier in the batch, are skipped like `push_back`.
+    /// Every element is encoded before anything is written, so if one can't be encoded, or is larger than `max_element_bytes`, this returns a HashQueueError and the queue is unchanged.
+    /// If the queue was opened with a capacity, and the batch doesn't fit, this returns `HashQueueError::CapacityExceeded` and the queue is unchanged.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// let result = hash_queue.push_back_batch(vec![1, 2, 2]).unwrap();
+    ///
+    /// assert_eq!(2, result);
+    /// assert_eq!(Some(2), hash_queue.back().unwrap());
+    /// ```
+    pub fn push_back_batch(&mut self, values: Vec<T>) -> Result<usize, HashQueueError> {
+        let mut previous = self.back()?;
+        let mut seen = HashSet::new();
+        let mut accepted = Vec::new();
+        let mut rejected_duplicates = 0;
+        for value in values {
+            let rejected = match self.options.dedup {
+                DedupMode::Global => self.set.contains(&value) || !seen.insert(value.clone()),
+                DedupMode::AdjacentOnly => previous.as_ref() == Some(&value),
+                DedupMode::Off => false,
+            };
+            previous = Some(value.clone());
+            if rejected {
+                rejected_duplicates += 1;
+                continue;
+            }
+            let val = self.encode(&value)?;
+            self.check_size(val.len())?;
+            accepted.push((value, val));
+        }
+        if let Some(capacity) = self.options.capacity {
+            if self.len() + accepted.len() > capacity {
+                return Err(HashQueueError::CapacityExceeded { capacity });
+            }
+        }
+        if accepted.is_empty() {
+            self.rejected_duplicates += rejected_duplicates;
+            return Ok(0);
+        }
+
+        self.invalidate_snapshot()?;
+        loop {
+            let first = self.back_index()?;
+            let last = first.checked_add(accepted.len() as i64 - 1).ok_or(HashQueueError::IndexOverflow { index: first })?;
+            // Another handle on the same tree may have pushed since back_index read it, in which case the transaction aborts and the batch is placed after its push.
+            let result: TransactionResult<(), ()> = self.tree.transaction(|tree| {
+                for (index, (_value, val)) in (first..=last).zip(&accepted) {
+                    if tree.insert(&key_from_index(index), val.as_slice())?.is_some() {
+                        return Err(ConflictableTransactionError::Abort(()));
+                    }
+                }
+                Ok(())
+            });
+            #[cfg(test)]
+            {
+                self.forced_back_index = None;
+            }
+            match result {
+                Ok(()) => break,
+                Err(TransactionError::Storage(error)) => return Err(error.into()),
+                Err(TransactionError::Abort(())) => continue,
+            }
+        }
+        self.rejected_duplicates += rejected_duplicates;
+        let added = accepted.len();
+        for (value, _val) in accepted {
+            self.track(value);
+            telemetry::record_push(&self.name, self.item_count());
+        }
+        self.autoflush()?;
+        Ok(added)
+    }
+
+    ///Name: export
+    ///
+    /// Desc: This function writes every element of the queue to `writer`, as the ordered index and serialized bytes pairs yielded by `iter_raw`, so the queue can be
+    /// backed up to a single file and restored with `import`, without depending on sled's on-disk format. The queue is not modified.
+    ///
+    /// Additional notes: The elements are written in the queue's codec, so the queue they are imported into has to be opened with the same codec.
+    ///                   Delayed elements are exported like any other element, and are visible straight away once imported.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1u64).unwrap();
+    ///
+    /// let mut backup = Vec::new();
+    /// hash_queue.export(&mut backup).unwrap();
+    ///
+    /// assert_eq!(true, !backup.is_empty());
+    /// ```
+    pub fn export<W: Write>(&self, writer: W) -> Result<(), HashQueueError> {
+        let pairs = self.iter_raw()
+            .map(|entry| entry.map(|(index, val)| (index, val.to_vec())))
+            .collect::<Result<Vec<(i64, Vec<u8>)>, HashQueueError>>()?;
+        bincode::serialize_into(writer, &pairs)?;
+        Ok(())
+    }
+
+    ///Name: import
+    ///
+    /// Desc: This function reads a queue written by `export` from `reader`, writes its elements at the same indices with `apply_raw`, and returns how many were read.
+    /// The set is rebuilt from the tree afterwards, so the imported elements are tracked like any other. Importing into an empty queue restores the exported queue in the same order.
+    ///
+    /// Additional notes: Like `apply_raw`, an element at an index that's already in use overwrites the element stored there, and if any element doesn't deserialize,
+    ///                   this function will return a `HashQueueError` and the queue is unchanged.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    /// hash_queue.push_back(1u64).unwrap();
+    /// let mut backup = Vec::new();
+    /// hash_queue.export(&mut backup).unwrap();
+    ///
+    /// let mut restored: HashQueue<u64> = HashQueue::open_temp("test").unwrap();
+    /// let result = restored.import(backup.as_slice()).unwrap();
+    ///
+    /// assert_eq!(1, result);
+    /// assert_eq!(Some(1), restored.front().unwrap());
+    /// ```
+    pub fn import<R: Read>(&mut self, reader: R) -> Result<usize, HashQueueError> {
+        let pairs: Vec<(i64, Vec<u8>)> = bincode::deserialize_from(reader)?;
+        let count = pairs.len();
+        self.apply_raw(pairs.into_iter())?;
+        Ok(count)
+    }
+
+    ///Name: retain
+    ///
+    /// Desc: This function removes every element for which `f` returns false, leaving the order of the remaining elements unchanged, and returns how many were removed.
+    /// This is intended for dropping elements that are no longer needed, like expired jobs. The elements are removed in a single batch, so the queue is never seen with only some of them removed.
+    /// If an element can't be deserialized, this will return a HashQueueError and the queue is unchanged.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    /// hash_queue.push_back(2).unwrap();
+    ///
+    /// let result = hash_queue.retain(|item| item % 2 == 0).unwrap();
+    ///
+    /// assert_eq!(1, result);
+    /// assert_eq!(Some(2), hash_queue.front().unwrap());
+    /// ```
+    pub fn retain<F: FnMut(&T) -> bool>(&mut self, mut f: F) -> Result<usize, HashQueueError> {
+        let mut removed = Vec::new();
+        for entry in self.tree.iter() {
+            let (key, val) = entry?;
+            let item: T = self.decode(&val)?;
+            if !f(&item) {
+                removed.push((key, item));
+            }
+        }
+        if removed.is_empty() {
+            return Ok(0);
+        }
+
+        self.invalidate_snapshot()?;
+        let mut batch = Batch::default();
+        for (key, _item) in &removed {
+            batch.remove(key);
+        }
+        self.tree.apply_batch(batch)?;
+        for (key, item) in &removed {
+            self.forget_delay(key)?;
+            if !self.release(item) {
+                return Err(self.desync("retain"));
+            }
+        }
+        self.autoflush()?;
+        telemetry::record_evictions(&self.name, removed.len(), self.item_count());
+        Ok(removed.len())
+    }
+
+    ///Name: peek_both
+    ///
+    /// Desc: This function returns the front and back of the queue together, like calling `front` and `back`, with one lookup for each end.
+    /// This is intended for displaying the range of a queue. The queue is not modified.
+    ///
+    /// Additional notes: When the queue holds a single element, it's only deserialized once and returned as both the front and the back.
+    ///                   Like `front`, delayed elements are skipped for the front, but like `back`, they aren't skipped for the back.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    /// hash_queue.push_back(2).unwrap();
+    ///
+    /// assert_eq!((Some(1), Some(2)), hash_queue.peek_both().unwrap());
+    /// ```
+    pub fn peek_both(&self) -> Result<(Option<T>, Option<T>), HashQueueError> {
+        let front = self.first_visible()?;
+        let Some((back_key, back_val)) = self.tree.last()? else {
+            return Ok((None, None));
+        };
+        let back: T = self.decode(&back_val)?;
+        match front {
+            Some((front_key, _val)) if front_key == back_key => Ok((Some(back.clone()), Some(back))),
+            Some((_key, front_val)) => Ok((Some(self.decode(&front_val)?), Some(back))),
+            None => Ok((None, Some(back))),
+        }
+    }
+
+    ///Name: with_front
+    ///
+    /// Desc: This function deserializes the front of the queue, if it exists, and returns what `f` returns when called with a reference to it.
+    /// This is intended for large elements where only part of the element is needed, like a single field, since nothing has to be cloned out of it. The queue is not modified.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back("hello".to_string()).unwrap();
+    ///
+    /// let result = hash_queue.with_front(|item| item.len()).unwrap();
+    ///
+    /// assert_eq!(Some(5), result);
+    /// ```
+    pub fn with_front<R, F: FnOnce(&T) -> R>(&self, f: F) -> Result<Option<R>, HashQueueError> {
+        match self.first_visible()? {
+            Some((_key, val)) => {
+                let item: T = self.decode(&val)?;
+                Ok(Some(f(&item)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    ///Name: rejected_count
+    ///
+    /// Desc: This function returns how many pushes this handle has rejected as duplicates since the queue was opened, whichever `DedupMode` is in use.
+    /// This shows how much the deduplication is actually doing, for example when pushing a stream that repeats itself. Pushes that fail with an error aren't counted.
+    ///
+    /// Additional notes: The count isn't stored on disk, so it starts from `0` every time the queue is opened.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    /// hash_queue.push_back(1).unwrap();
+    ///
+    /// assert_eq!(1, hash_queue.rejected_count());
+    /// ```
+    pub fn rejected_count(&self) -> u64 {
+        self.rejected_duplicates
+    }
+
+    ///Name: stats
+    ///
+    /// Desc: This function returns a summary of the queue for dashboards and health endpoints: its name, length, how many duplicates it has rejected, and its front and back elements.
+    /// It is cheap to call, since it only reads the two ends of the queue rather than scanning it. Use `to_string` on the result for a one line summary.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    /// hash_queue.push_back(2).unwrap();
+    ///
+    /// let result = hash_queue.stats().unwrap();
+    ///
+    /// assert_eq!(2, result.len);
+    /// assert_eq!("test: 2 elements, 0 rejected duplicates, front Some(1), back Some(2)", result.to_string());
+    /// ```
+    pub fn stats(&self) -> Result<QueueStats<T>, HashQueueError> {
+        Ok(QueueStats {
+            name: self.name.clone(),
+            len: self.len(),
+            rejected_duplicates: self.rejected_duplicates,
+            front: self.front()?,
+            back: self.back()?,
+        })
+    }
+
+    ///Name: rebuild_set
+    ///
+    /// Desc: This function throws away the set, and builds it again by deserializing every element in the tree. This is the recovery path for a set that has drifted
+    /// from the tree, for example after a `HashQueueError::SyncError`. Unlike `reconcile`, elements that are in the tree but missing from the set are added back.
+    /// Once the set has been rebuilt, the queue is no longer reported as poisoned by `health_check`.
+    ///
+    /// Additional notes: When the queue was opened with `DedupMode::Global`, the tree should never hold the same value twice. If it does, this returns a `HashQueueError::SyncError`,
+    ///                   and the set is left unchanged. With `DedupMode::AdjacentOnly` or `DedupMode::Off`, repeated values are allowed, and are counted like they are when the queue is opened.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    /// hash_queue.rebuild_set().unwrap();
+    ///
+    /// assert_eq!(true, hash_queue.contains(&1));
+    /// ```
+    pub fn rebuild_set(&mut self) -> Result<(), HashQueueError> {
+        let (set, duplicates) = Self::collect_set(&self.tree, &self.options)?;
+        if self.options.dedup == DedupMode::Global && !duplicates.is_empty() {
+            return Err(HashQueueError::SyncError {
+                message: format!("rebuild_set: {} values are stored more than once", duplicates.len()),
+            });
+        }
+        self.invalidate_snapshot()?;
+        self.set = set;
+        self.duplicates = duplicates;
+        self.poisoned.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+
+    ///Name: size_on_disk
+    ///
+    /// Desc: This function returns how many bytes the queue's database takes up on disk, using sled's `Db::size_on_disk`. This is intended for capacity planning.
+    /// The size is read from the database's files, so this is cheap no matter how long the queue is.
+    ///
+    /// Additional notes: This covers the whole database, not just the queue: sled's own overhead, the queue's sidecar trees, every other tree in the database,
+    ///                   and space sled hasn't reclaimed yet from removed elements. It doesn't shrink straight away when the queue does (see `compact`).
+    ///                   A queue opened with `from_tree` has no database to measure, so this returns `HashQueueError::Unsupported`.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back("a".repeat(10_000)).unwrap();
+    /// hash_queue.flush().unwrap();
+    ///
+    /// assert!(hash_queue.size_on_disk().unwrap() > 10_000);
+    /// ```
+    pub fn size_on_disk(&self) -> Result<u64, HashQueueError> {
+        let Some(db) = &self.db else {
+            return Err(HashQueueError::Unsupported {
+                message: "size_on_disk: the queue was opened with from_tree, so it has no database to measure".to_string(),
+            });
+        };
+        Ok(db.size_on_disk()?)
+    }
+
+    ///Name: compact
+    ///
+    /// Desc: This function is a maintenance hook for long running queues. It flushes the queue like `flush`, and then flushes the rest of the database,
+    /// so every write, including writes to the sidecar trees, is on disk, and sled is free to reuse the space held by removed elements.
+    ///
+    /// Additional notes: Sled doesn't offer a way to reclaim space on demand. Its segment cleaner runs in the background, and rewrites segments that are
+    ///                   mostly made up of removed data as later writes come in, so the file shrinks, or stops growing, over time rather than when this returns.
+    ///                   Under steady churn, the file settles at a size proportional to how much the queue holds, not to how many elements have passed through it.
+    ///                   The only way to get a minimal file straight away is to `export` the queue and `import` it into a new database.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    /// hash_queue.pop_front().unwrap();
+    ///
+    /// hash_queue.compact().unwrap();
+    ///
+    /// assert_eq!(true, hash_queue.is_empty());
+    /// assert_eq!(true, hash_queue.push_back(1).unwrap());
+    /// ```
+    pub fn compact(&mut self) -> Result<(), HashQueueError> {
+        self.flush()?;
+        if let Some(db) = &self.db {
+            db.flush()?;
+        }
+        Ok(())
+    }
+
+    ///Name: push_with_priority
+    ///
+    /// Desc: This function pushes an element onto a queue opened with `open_priority`, where it's kept in order of `priority`, so elements with a lower priority are popped from the front first.
+    /// Elements with the same priority are popped in the order they were pushed. Like `push_back`, this returns ```Ok(true)``` if the element was added,
+    /// and ```Ok(false)``` if it was already in the queue. The element keeps its priority if it is already in the queue with a different one.
+    /// If the queue isn't ordered by priority, this returns `HashQueueError::Unsupported` without modifying the queue.
+    ///
+    /// Additional notes: Each element is stored under its priority, followed by a sequence number from sled's `generate_id`, so pushes never collide,
+    ///                   even from handles on different trees of the same database. With `DedupMode::AdjacentOnly` no element is rejected, since elements
+    ///                   aren't stored next to the one pushed before them.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let dir = tempfile::tempdir().unwrap();
+    /// let mut hash_queue = HashQueue::open_priority(dir.path(), "test").unwrap();
+    ///
+    /// hash_queue.push_with_priority(1, 10).unwrap();
+    /// hash_queue.push_with_priority(2, -10).unwrap();
+    ///
+    /// assert_eq!(Some(2), hash_queue.front().unwrap());
+    /// assert_eq!(false, hash_queue.push_with_priority(1, 0).unwrap());
+    /// ```
+    pub fn push_with_priority(&mut self, value: T, priority: i64) -> Result<bool, HashQueueError> {
+        let (true, Some(db)) = (self.options.priority, &self.db) else {
+            return Err(HashQueueError::Unsupported {
+                message: "push_with_priority: the queue isn't ordered by priority, open it with open_priority".to_string(),
+            });
+        };
+        self.check_push(&value)?;
+        if self.options.dedup == DedupMode::Global && self.set.contains(&value) {
+            self.rejected_duplicates += 1;
+            return Ok(false);
+        }
+        let key = priority_key(priority, db.generate_id()?);
+        let val = self.encode(&value)?;
+        self.invalidate_snapshot()?;
+        self.tree.insert(key, val)?;
+        self.track(value);
+        self.finish_push(true)?;
+        Ok(true)
+    }
+
+    ///Name: get_or_push
+    ///
+    /// Desc: This function pushes an element onto the back of the queue if it isn't already in it, and reports which of the two happened as a `PushOutcome`.
+    /// This is the same as `push_back`, but says what the returned bool means, so the caller knows the element is pending either way without a separate call to `contains`.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::{HashQueue, PushOutcome};
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// assert_eq!(PushOutcome::Inserted, hash_queue.get_or_push(1).unwrap());
+    /// assert_eq!(PushOutcome::AlreadyPresent, hash_queue.get_or_push(1).unwrap());
+    /// ```
+    pub fn get_or_push(&mut self, value: T) -> Result<PushOutcome, HashQueueError> {
+        match self.push_back(value)? {
+            true => Ok(PushOutcome::Inserted),
+            false => Ok(PushOutcome::AlreadyPresent),
+        }
+    }
+
+    ///Name: reader
+    ///
+    /// Desc: This function returns a read-only view of the queue, which can be cloned and shared across threads without a lock. See `HashQueueReader`.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    /// let reader = hash_queue.reader();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    ///
+    /// assert_eq!(Some(1), reader.front().unwrap());
+    /// ```
+    pub fn reader(&self) -> HashQueueReader<T> {
+        HashQueueReader {
+            tree: self.tree.clone(),
+            options: self.options.clone(),
+            item: PhantomData,
+        }
+    }
+
+    ///Name: iter_rev
+    ///
+    /// Desc: This function iterates over the queue from back to front, deserializing each element as the iterator reaches it, so the most recently pushed elements come first.
+    /// Like `iter`, the queue is not modified, delayed elements are included, and each element is yielded as a `Result`.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    /// hash_queue.push_back(2).unwrap();
+    ///
+    /// let result = hash_queue.iter_rev().collect::<Result<Vec<i32>, _>>().unwrap();
+    ///
+    /// assert_eq!(vec![2, 1], result);
+    /// ```
+    pub fn iter_rev(&self) -> impl Iterator<Item = Result<T, HashQueueError>> + '_ {
+        self.tree.iter().values().rev().map(|val| self.decode(&val?))
+    }
+
+    ///Name: pop_front_peek_next
+    ///
+    /// Desc: This function pops the front of the queue like `pop_front`, and also returns the element that is at the front once it has been popped, like `front`.
+    /// This is intended for consumer loops that want to know what comes next as soon as they take an element. If the queue is empty, this returns `(None, None)`.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    /// hash_queue.push_back(2).unwrap();
+    ///
+    /// assert_eq!((Some(1), Some(2)), hash_queue.pop_front_peek_next().unwrap());
+    /// assert_eq!((Some(2), None), hash_queue.pop_front_peek_next().unwrap());
+    /// ```
+    pub fn pop_front_peek_next(&mut self) -> Result<(Option<T>, Option<T>), HashQueueError> {
+        let popped = self.pop_front()?;
+        if popped.is_none() {
+            return Ok((None, None));
+        }
+        Ok((popped, self.front()?))
+    }
+
+    ///Name: push_or_update
+    ///
+    /// Desc: This function pushes an element onto the back of the queue, or, if an equal element is already in the queue, overwrites the stored element with `value`
+    /// without moving it. This is intended for elements whose `Eq` only compares an identifier, so a newer copy can refresh the rest of the element while it keeps its place.
+    /// It returns ```Ok(true)``` if an element was updated, and ```Ok(false)``` if `value` was pushed as a new element.
+    ///
+    /// Additional notes: Whether the element is present is checked with the set, so pushing a new element doesn't scan the queue. Finding an element to update scans
+    ///                   the queue from the front, like `remove`. If the queue holds more than one copy, only the copy closest to the front is updated.
+    ///                   If another handle on the tree changes or removes the element after it's found, this returns `HashQueueError::SyncError` without writing anything.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// assert_eq!(false, hash_queue.push_or_update(1).unwrap());
+    /// assert_eq!(true, hash_queue.push_or_update(1).unwrap());
+    /// assert_eq!(1, hash_queue.len());
+    /// ```
+    pub fn push_or_update(&mut self, value: T) -> Result<bool, HashQueueError> {
+        //An element that isn't in the set can't be rejected as a duplicate, so `push_back` always pushes it here.
+        if !self.set.contains(&value) {
+            self.push_back(value)?;
+            return Ok(false);
+        }
+        let (key, old_val) = self.find_entry(&value, "push_or_update")?.ok_or_else(|| self.desync("push_or_update"))?;
+        let val = self.encode(&value)?;
+        self.check_size(val.len())?;
+        self.invalidate_snapshot()?;
+        //Like `replace`, the element is only overwritten if another handle on the tree hasn't changed or removed it since it was found.
+        if self.tree.compare_and_swap(key, Some(old_val), Some(val))?.is_err() {
+            return Err(HashQueueError::SyncError {
+                message: "push_or_update: the element was changed or removed by another handle on the tree".to_string(),
+            });
+        }
+        self.set.replace(value);
+        self.autoflush()?;
+        Ok(true)
+    }
+
+}
+
+//A queue that is dropped without being flushed, like one with autoflush off or a write buffer that hasn't filled up, would otherwise leave its last writes to sled's
+//background flush. Errors can't be returned from drop, so a failed flush is ignored here, call `flush` or `close` to find out whether the writes were made durable.
+impl<T> Drop for HashQueue<T> {
+    fn drop(&mut self) {
+        let _ = self.tree.flush();
+    }
+}
+
+///Name: PushOutcome
+///
+/// Desc: What happened to an element pushed with `HashQueue::get_or_push`. Either way, the element is in the queue once the push returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PushOutcome {
+    /// The element wasn't in the queue, and has been pushed onto the back.
+    Inserted,
+    /// The element was already in the queue, so it was left where it was.
+    AlreadyPresent,
+}
+
+///Name: QueueEvent
+///
+/// Desc: A change to a queue's tree, received through a `Subscription`.
+///
+/// Additional notes: Events describe the tree, not the operation that changed it. Anything that writes an element is reported as `Pushed`, including `replace`
+///                   and `rotate_n`, which move or rewrite elements already in the queue. Anything that removes an element is reported as `Removed`, including `clear` and `remove`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueueEvent<T> {
+    /// An element was written to the tree at `index`.
+    Pushed { index: i64, item: T },
+    /// The element at `index` was removed from the tree.
+    Removed { index: i64 },
+}
+
+///Name: Subscription
+///
+/// Desc: The subscription returned by `HashQueue::subscribe`. Iterating over it blocks until the next change to the queue, and yields it as a `QueueEvent`.
+/// An element that can't be decoded is yielded as a HashQueueError. The iterator only ends once the queue's database has been closed.
+pub struct Subscription<T> {
+    subscriber: sled::Subscriber,
+    options: HashQueueOptions,
+    item: PhantomData<T>,
+}
+
+impl<T> Subscription<T>
+    where
+        for<'de> T: Deserialize<'de>,
+{
+    ///Name: next_timeout
+    ///
+    /// Desc: This function waits up to `timeout` for the next change to the queue. It returns `None` if nothing changed before the timeout, or if the database has been closed.
+    pub fn next_timeout(&mut self, timeout: Duration) -> Option<Result<QueueEvent<T>, HashQueueError>> {
+        self.subscriber.next_timeout(timeout).ok().map(|event| self.convert(event))
+    }
+
+    //This is an internal function that turns one of sled's events into a QueueEvent.
+    fn convert(&self, event: Event) -> Result<QueueEvent<T>, HashQueueError> {
+        match event {
+            Event::Insert { key, value } => Ok(QueueEvent::Pushed {
+                index: index_from_key(&key),
+                item: self.options.decode(&value)?,
+            }),
+            Event::Remove { key } => Ok(QueueEvent::Removed {
+                index: index_from_key(&key),
+            }),
+        }
+    }
+}
+
+impl<T> Iterator for Subscription<T>
+    where
+        for<'de> T: Deserialize<'de>,
+{
+    type Item = Result<QueueEvent<T>, HashQueueError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let event = self.subscriber.next()?;
+        Some(self.convert(event))
+    }
+}
+
+///Name: IntoIter
+///
+/// Desc: The iterator returned by `HashQueue::into_iter`. It pops elements from the front of the queue until the queue is empty, so they are removed from disk as they are yielded.
+/// Each element is yielded as a `Result`, like `pop_front`. After an error is yielded, the iterator ends, and the queue is dropped with the remaining elements still in it.
+/// Delayed elements aren't popped, so they are left in the queue too.
+pub struct IntoIter<T> {
+    queue: HashQueue<T>,
+    done: bool,
+}
+
+impl<T> Iterator for IntoIter<T>
+    where
+        T: Hash + Eq + Clone + Serialize + Debug,
+        for<'de> T: Deserialize<'de>,
+{
+    type Item = Result<T, HashQueueError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.queue.pop_front() {
+            Ok(Some(item)) => Some(Ok(item)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(error) => {
+                self.done = true;
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+impl<T> IntoIterator for HashQueue<T>
+    where
+        T: Hash + Eq + Clone + Serialize + Debug,
+        for<'de> T: Deserialize<'de>,
+{
+    type Item = Result<T, HashQueueError>;
+    type IntoIter = IntoIter<T>;
+
+    ///Name: into_iter
+    ///
+    /// Desc: This function consumes the queue, returning an iterator that pops every element from the front of the queue. See `IntoIter`.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.push_back(1).unwrap();
+    /// hash_queue.push_back(2).unwrap();
+    ///
+    /// let result = hash_queue.into_iter().collect::<Result<Vec<i32>, _>>().unwrap();
+    ///
+    /// assert_eq!(vec![1, 2], result);
+    /// ```
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter {
+            queue: self,
+            done: false,
+        }
+    }
+}
+
+impl<T> Extend<T> for HashQueue<T>
+    where
+        T: Hash + Eq + Clone + Serialize + Debug,
+        for<'de> T: Deserialize<'de>,
+{
+    ///Name: extend
+    ///
+    /// Desc: This function pushes every element of `iter` onto the back of the queue, skipping elements that are already in the queue. See `extend_back`.
+    ///
+    /// Additional notes: `Extend` can't report an error, so this panics if a push fails. Use `extend_back` to handle the error instead.
+    ///
+    /// Usage:
+    ///```
+    /// use set_deque::hash_queue::HashQueue;
+    ///
+    /// let mut hash_queue = HashQueue::open_temp("test").unwrap();
+    ///
+    /// hash_queue.extend(vec![1, 2]);
+    ///
+    /// assert_eq!(Some(2), hash_queue.back().unwrap());
+    /// ```
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        self.extend_back(iter).expect("extend: failure to push to queue");
+    }
+}
+
+///Name: HashQueueReader
+///
+/// Desc: A read-only view of a queue, returned by `HashQueue::reader`. It holds a clone of the queue's tree, so it is cheap to clone, can be sent to other threads,
+/// and always sees the queue as it currently is, including pushes and pops made through the `HashQueue` after the reader was created.
+///
+/// Additional notes: The set isn't shared with the reader, so `contains` scans the tree instead, and the sidecar trees aren't either, so delayed and expired elements
+///                   aren't skipped. Elements are decoded with the codec the queue was opened with.
+pub struct HashQueueReader<T> {
+    tree: Tree,
+    options: HashQueueOptions,
+    item: PhantomData<T>,
+}
+
+impl<T> Clone for HashQueueReader<T> {
+    fn clone(&self) -> Self {
+        Self {
+            tree: self.tree.clone(),
+            options: self.options.clone(),
+            item: PhantomData,
+        }
+    }
+}
+
+impl<T> HashQueueReader<T>
+    where
+        T: PartialEq,
+        for<'de> T: Deserialize<'de>,
+{
+    ///Name: front
+    ///
+    /// Desc: This function returns the front of the queue, if it exists. See `HashQueue::front`.
+    pub fn front(&self) -> Result<Option<T>, HashQueueError> {
+        self.tree.first()?.map(|(_key, val)| self.options.decode(&val)).transpose()
+    }
+
+    ///Name: back
+    ///
+    /// Desc: This function returns the back of the queue, if it exists. See `HashQueue::back`.
+    pub fn back(&self) -> Result<Option<T>, HashQueueError> {
+        self.tree.last()?.map(|(_key, val)| self.options.decode(&val)).transpose()
+    }
+
+    ///Name: len
+    ///
+    /// Desc: This function returns how many elements are in the queue, by counting the entries in the tree.
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    ///Name: is_empty
+    ///
+    /// Desc: This function returns whether the queue is empty.
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    ///Name: contains
+    ///
+    /// Desc: This function returns whether `value` is in the queue. The reader doesn't have the set, so this deserializes elements from the front until it finds `value`.
+    pub fn contains(&self, value: &T) -> Result<bool, HashQueueError> {
+        for item in self.iter() {
+            if item? == *value {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    ///Name: iter
+    ///
+    /// Desc: This function iterates over the queue from front to back, deserializing each element as the iterator reaches it. See `HashQueue::iter`.
+    pub fn iter(&self) -> impl Iterator<Item = Result<T, HashQueueError>> + '_ {
+        self.tree.iter().values().map(|val| self.options.decode(&val?))
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
+mod tests{
+    use std::fmt::Debug;
+    use std::hash::Hash;
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use serde::{Deserialize, Serialize};
+    #[cfg(feature = "compression")]
+    use crate::codec::Deflate;
+    use crate::codec::{ByteCodec, Codec, CompositeCodec};
+    use crate::errors::HashQueueError;
+    use crate::hash_queue::{key_from_index, HashQueue, PushOutcome, QueueEvent, SNAPSHOT_KEY};
+    use crate::options::{CorruptPolicy, DedupMode, HashQueueOptions, WriteBuffer};
+    use crate::namespaced::NamespacedHashQueue;
+
+
+
+    /// This function is a basic start up that is used to initialize the set-deque and
+    fn test_setup<T:  Hash + Eq + Clone + Serialize + Debug + for<'de> Deserialize<'de>>(_: T) -> HashQueue<T>{
+        HashQueue::open_temp("test").unwrap()
+    }
+
+    //Tests that need options other than the defaults still get a temporary database of their own.
+    fn test_setup_with<T:  Hash + Eq + Clone + Serialize + Debug + for<'de> Deserialize<'de>>(options: HashQueueOptions) -> HashQueue<T>{
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        HashQueue::open_db(db, None, "test", options).unwrap()
+    }
+
+    //Tests that close the queue and open it again need a path that outlives the queue, rather than a temporary database.
+    //They pass a fresh temporary directory, so nothing is left over from an earlier run.
+    fn test_setup_at<T:  Hash + Eq + Clone + Serialize + Debug + for<'de> Deserialize<'de>>(_: T, path: &Path) -> HashQueue<T>{
+        HashQueue::open(path, "test").unwrap()
+    }
+
+    //Sled only releases the lock on a database once its background threads have stopped, which can be after the last handle is dropped.
+    //Tests that drop a database without calling close wait for the lock here before opening the path again, the same way close does.
+    fn wait_until_closed(path: &Path) {
+        let lock_file = std::fs::File::open(path.join("db")).unwrap();
+        while lock_file.try_lock().is_err() {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn should_add_to_hash_queue(){
+        let mut hash_queue = test_setup("1".to_string());
+        let result = hash_queue.push_back("1".to_string());
+        assert_eq!(true, result.unwrap());
+    }
+
+
+    #[test]
+    fn should_report_hash_queue_is_empty(){
+        let hash_queue= test_setup("1".to_string());
+        let result = hash_queue.is_empty();
+        assert_eq!(true, result);
+    }
+
+
+    #[test]
+    fn should_report_hash_queue_is_not_empty(){
+        let mut hash_queue= test_setup("1".to_string());
+        hash_queue.push_back("1".to_string()).unwrap();
+        let result = hash_queue.is_empty();
+        assert_eq!(false, result);
+    }
+
+    #[test]
+    fn should_see_front_of_hash_queue_and_dequeue(){
+        let mut hash_queue= test_setup(1u64);
+        hash_queue.push_back(1).unwrap();
+        let result = hash_queue.front().unwrap();
+        assert_eq!(Some(1), result);
+        let result = hash_queue.pop_front().unwrap();
+        assert_eq!(Some(1), result);
+    }
+
+
+    #[test]
+    fn should_fail_to_add_duplicate_item(){
+        let mut hash_queue = test_setup(1u64);
+        hash_queue.push_back(1).unwrap();
+        let result = hash_queue.push_back(1).unwrap();
+        assert_eq!(false, result);
+    }
+
+    #[test]
+    fn should_empty_hash_queue(){
+        let mut hash_queue = test_setup(1u64);
+        hash_queue.push_back(1).unwrap();
+        let result = hash_queue.front().unwrap();
+        assert_eq!(Some(1), result);
+        let result = hash_queue.pop_front().unwrap();
+        assert_eq!(Some(1), result);
+        assert_eq!(true, hash_queue.is_empty());
+    }
+
+    #[test]
+    fn should_produce_items_in_correct_order(){
+        let mut hash_queue= test_setup(1u64);
+
+        hash_queue.push_back(1).unwrap();
+        hash_queue.push_back(2).unwrap();
+        hash_queue.push_back(3).unwrap();
+        hash_queue.assert_keys_sorted();
+
+        let one = hash_queue.pop_front().unwrap();
+        let two = hash_queue.pop_front().unwrap();
+        let three = hash_queue.pop_front().unwrap();
+
+        assert_eq!(one, Some(1));
+        assert_eq!(two, Some(2));
+        assert_eq!(three, Some(3));
+    }
+
+    #[test]
+    fn should_produce_items_in_correct_order_reversed(){
+        let mut hash_queue = test_setup(1u64);
+
+        hash_queue.push_back(1).unwrap();
+        hash_queue.push_back(2).unwrap();
+        hash_queue.push_back(3).unwrap();
+        hash_queue.assert_keys_sorted();
+
+        let one = hash_queue.pop_back().unwrap();
+        let two = hash_queue.pop_back().unwrap();
+        let three = hash_queue.pop_back().unwrap();
+
+        assert_eq!(one, Some(3));
+        assert_eq!(two, Some(2));
+        assert_eq!(three, Some(1));
+    }
+
+    #[test]
+    fn should_report_name_from_open_str(){
+        let dir = tempfile::tempdir().unwrap();
+        let hash_queue: HashQueue<u64> = HashQueue::open_str(dir.path(), "named_queue").unwrap();
+        assert_eq!("named_queue", hash_queue.name());
+    }
+
+    #[test]
+    fn should_fold_over_items_in_queue(){
+        let mut hash_queue = test_setup(1u64);
+
+        hash_queue.push_back(1).unwrap();
+        hash_queue.push_back(2).unwrap();
+        hash_queue.push_back(3).unwrap();
+        hash_queue.push_back(4).unwrap();
+
+        let sum = hash_queue.fold(0, |sum, item| sum + item).unwrap();
+
+        assert_eq!(10, sum);
+        assert!(!hash_queue.is_empty());
+    }
+
+    #[test]
+    fn should_replace_item_in_place(){
+        let mut hash_queue = test_setup(1u64);
+
+        hash_queue.push_back(1).unwrap();
+        hash_queue.push_back(2).unwrap();
+        hash_queue.push_back(3).unwrap();
+
+        let result = hash_queue.replace(&2, 5).unwrap();
+
+        assert!(result);
+        assert_eq!(hash_queue.pop_front().unwrap(), Some(1));
+        assert_eq!(hash_queue.pop_front().unwrap(), Some(5));
+        assert_eq!(hash_queue.pop_front().unwrap(), Some(3));
+    }
+
+    #[test]
+    fn should_not_replace_missing_item(){
+        let mut hash_queue = test_setup(1u64);
+
+        hash_queue.push_back(1).unwrap();
+
+        let result = hash_queue.replace(&2, 5).unwrap();
+
+        assert!(!result);
+        assert_eq!(hash_queue.pop_front().unwrap(), Some(1));
+        assert!(hash_queue.is_empty());
+    }
+
+    #[test]
+    fn should_not_replace_with_colliding_item(){
+        let mut hash_queue = test_setup(1u64);
+
+        hash_queue.push_back(1).unwrap();
+        hash_queue.push_back(2).unwrap();
+
+        let result = hash_queue.replace(&1, 2).unwrap();
+
+        assert!(!result);
+        assert_eq!(hash_queue.pop_front().unwrap(), Some(1));
+        assert_eq!(hash_queue.pop_front().unwrap(), Some(2));
+    }
+
+    #[test]
+    fn should_not_replace_with_element_over_size_limit(){
+        let options = HashQueueOptions {
+            max_element_bytes: Some(12),
+            ..HashQueueOptions::default()
+        };
+        let mut hash_queue: HashQueue<String> = test_setup_with(options);
+        hash_queue.push_back("abcd".to_string()).unwrap();
+
+        let result = hash_queue.replace(&"abcd".to_string(), "abcde".to_string());
+
+        assert!(matches!(result, Err(HashQueueError::ElementTooLarge { size: 13, max: 12 })), "{:?}", result);
+        assert_eq!(Some("abcd".to_string()), hash_queue.front().unwrap());
+        assert!(hash_queue.contains(&"abcd".to_string()));
+        assert!(!hash_queue.contains(&"abcde".to_string()));
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn should_record_metrics_for_operations(){
+        use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+        use metrics_util::MetricKind;
+        use crate::telemetry;
+
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+
+        metrics::with_local_recorder(&recorder, || {
+            let mut hash_queue = test_setup(1u64);
+            hash_queue.push_back(1).unwrap();
+            hash_queue.push_back(2).unwrap();
+            hash_queue.push_back(2).unwrap();
+            hash_queue.push_back(3).unwrap();
+            hash_queue.pop_front().unwrap();
+            hash_queue.clear().unwrap();
+        });
+
+        let snapshot = snapshotter.snapshot().into_hashmap();
+        let value_of = |kind: MetricKind, name: &str| {
+            snapshot.iter()
+                .find(|(key, _)| key.kind() == kind && key.key().name() == name)
+                .map(|(_, (_, _, value))| value)
+        };
+
+        assert_eq!(Some(&DebugValue::Counter(3)), value_of(MetricKind::Counter, telemetry::PUSHES_TOTAL));
+        assert_eq!(Some(&DebugValue::Counter(1)), value_of(MetricKind::Counter, telemetry::POPS_TOTAL));
+        assert_eq!(Some(&DebugValue::Counter(2)), value_of(MetricKind::Counter, telemetry::EVICTIONS_TOTAL));
+        assert_eq!(Some(&DebugValue::Gauge(0.0.into())), value_of(MetricKind::Gauge, telemetry::QUEUE_LENGTH));
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn should_record_appended_items_as_pops(){
+        use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+        use metrics_util::MetricKind;
+        use crate::telemetry;
+
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+
+        metrics::with_local_recorder(&recorder, || {
+            let mut hash_queue = test_setup(1u64);
+            let mut other = test_setup(1u64);
+            other.extend_back(vec![1, 2]).unwrap();
+            hash_queue.append(&mut other).unwrap();
+        });
+
+        let snapshot = snapshotter.snapshot().into_hashmap();
+        let value_of = |kind: MetricKind, name: &str| {
+            snapshot.iter()
+                .find(|(key, _)| key.kind() == kind && key.key().name() == name)
+                .map(|(_, (_, _, value))| value)
+        };
+
+        assert_eq!(Some(&DebugValue::Counter(2)), value_of(MetricKind::Counter, telemetry::POPS_TOTAL));
+        assert_eq!(None, value_of(MetricKind::Counter, telemetry::EVICTIONS_TOTAL));
+    }
+
+    #[test]
+    fn should_report_distance_between_items(){
+        let mut hash_queue = test_setup("x".to_string());
+
+        hash_queue.push_back("x".to_string()).unwrap();
+        hash_queue.push_back("y".to_string()).unwrap();
+        hash_queue.push_back("z".to_string()).unwrap();
+
+        assert_eq!(Some(-2), hash_queue.distance(&"x".to_string(), &"z".to_string()).unwrap());
+        assert_eq!(Some(2), hash_queue.distance(&"z".to_string(), &"x".to_string()).unwrap());
+        assert_eq!(Some(0), hash_queue.distance(&"y".to_string(), &"y".to_string()).unwrap());
+        assert_eq!(None, hash_queue.distance(&"x".to_string(), &"w".to_string()).unwrap());
+    }
+
+    #[test]
+    fn should_read_and_persist_buffered_pushes(){
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path();
+        let options = HashQueueOptions {
+            write_buffer: Some(WriteBuffer { capacity: 3, max_delay: Duration::from_secs(60) }),
+            ..HashQueueOptions::default()
+        };
+        {
+            let mut hash_queue: HashQueue<u64> = HashQueue::open_with_options(path, "test", options.clone()).unwrap();
+
+            hash_queue.push_back(1).unwrap();
+            hash_queue.push_back(2).unwrap();
+            assert_eq!(2, hash_queue.pending_writes);
+            assert_eq!(Some(1), hash_queue.front().unwrap());
+            assert_eq!(Some(2), hash_queue.back().unwrap());
+
+            hash_queue.push_back(3).unwrap();
+            assert_eq!(0, hash_queue.pending_writes);
+        }
+        let hash_queue: HashQueue<u64> = HashQueue::open_with_options(path, "test", options).unwrap();
+        let items = hash_queue.fold(Vec::new(), |mut items, item| { items.push(item); items }).unwrap();
+        assert_eq!(vec![1, 2, 3], items);
+    }
+
+    #[test]
+    fn should_track_next_back_index(){
+        let mut hash_queue = test_setup(1u64);
+        assert_eq!(0, hash_queue.peek_next_back_index().unwrap());
+
+        hash_queue.push_back(1).unwrap();
+        assert_eq!(1, hash_queue.peek_next_back_index().unwrap());
+
+        hash_queue.force_next_back_index(10);
+        assert_eq!(10, hash_queue.peek_next_back_index().unwrap());
+        hash_queue.push_back(2).unwrap();
+        assert_eq!(11, hash_queue.peek_next_back_index().unwrap());
+    }
+
+    #[test]
+    fn should_fail_to_push_past_max_index(){
+        let mut hash_queue = test_setup(1u64);
+
+        hash_queue.force_next_back_index(i64::MAX - 1);
+        hash_queue.push_back(1).unwrap();
+        hash_queue.push_back(2).unwrap();
+
+        let result = hash_queue.push_back(3);
+
+        assert!(matches!(result, Err(HashQueueError::IndexOverflow { index: i64::MAX })));
+        assert_eq!(Some(2), hash_queue.back().unwrap());
+        assert_eq!(Some(2), hash_queue.pop_back().unwrap());
+        assert_eq!(Some(1), hash_queue.pop_back().unwrap());
+        assert!(hash_queue.is_empty());
+    }
+
+    #[test]
+    fn should_only_reject_adjacent_duplicates(){
+        let options = HashQueueOptions {
+            dedup: DedupMode::AdjacentOnly,
+            ..HashQueueOptions::default()
+        };
+        let mut hash_queue: HashQueue<u64> = test_setup_with(options);
+
+        assert!(hash_queue.push_back(1).unwrap());
+        assert!(!hash_queue.push_back(1).unwrap());
+        assert!(hash_queue.push_back(2).unwrap());
+        assert!(hash_queue.push_back(1).unwrap());
+
+        assert_eq!(Some(1), hash_queue.pop_front().unwrap());
+        assert_eq!(Some(2), hash_queue.pop_front().unwrap());
+        assert!(!hash_queue.is_empty());
+        assert_eq!(Some(1), hash_queue.pop_front().unwrap());
+        assert!(hash_queue.is_empty());
+    }
+
+    #[test]
+    fn should_allow_duplicates_when_dedup_is_off(){
+        let options = HashQueueOptions {
+            dedup: DedupMode::Off,
+            ..HashQueueOptions::default()
+        };
+        let mut hash_queue: HashQueue<u64> = test_setup_with(options);
+
+        assert!(hash_queue.push_back(1).unwrap());
+        assert!(hash_queue.push_back(1).unwrap());
+        assert!(hash_queue.push_front(1).unwrap());
+
+        assert_eq!(3, hash_queue.len());
+        assert_eq!(hash_queue.len(), hash_queue.tree.len());
+        for _ in 0..3 {
+            assert_eq!(Some(1), hash_queue.pop_front().unwrap());
+        }
+        assert!(hash_queue.is_empty());
+        assert!(!hash_queue.contains(&1));
+    }
+
+    #[test]
+    fn should_reopen_after_close(){
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path();
+        let mut hash_queue = test_setup_at(1u64, path);
+        hash_queue.push_back(1).unwrap();
+
+        hash_queue.close().unwrap();
+
+        let hash_queue: HashQueue<u64> = HashQueue::open(path, "test").unwrap();
+        assert_eq!(Some(1), hash_queue.front().unwrap());
+    }
+
+    #[test]
+    fn should_group_items_by_key(){
+        let mut hash_queue = test_setup(("a".to_string(), 1u64));
+
+        hash_queue.push_back(("a".to_string(), 1)).unwrap();
+        hash_queue.push_back(("b".to_string(), 2)).unwrap();
+        hash_queue.push_back(("a".to_string(), 3)).unwrap();
+
+        let groups = hash_queue.group_by(|(tenant, _)| tenant.clone()).unwrap();
+
+        assert_eq!(2, groups.len());
+        assert_eq!(vec![("a".to_string(), 1), ("a".to_string(), 3)], groups["a"]);
+        assert_eq!(vec![("b".to_string(), 2)], groups["b"]);
+    }
+
+    #[test]
+    fn should_drop_items_from_front(){
+        let mut hash_queue = test_setup(1u64);
+
+        hash_queue.push_back(1).unwrap();
+        hash_queue.push_back(2).unwrap();
+        hash_queue.push_back(3).unwrap();
+        hash_queue.push_back(4).unwrap();
+
+        let removed = hash_queue.drop_front(2).unwrap();
+
+        assert_eq!(2, removed);
+        assert_eq!(hash_queue.pop_front().unwrap(), Some(3));
+        assert_eq!(hash_queue.pop_front().unwrap(), Some(4));
+        assert!(hash_queue.is_empty());
+        assert_eq!(0, hash_queue.drop_front(2).unwrap());
+    }
+
+    #[test]
+    fn should_leave_corrupt_front_in_place_when_dropping(){
+        let mut hash_queue = corrupt_front_setup(CorruptPolicy::Fail);
+
+        let result = hash_queue.drop_front(2);
+        assert!(matches!(result, Err(HashQueueError::BinCodeError { .. })), "{:?}", result);
+        assert_eq!(3, hash_queue.tree.len());
+        assert!(hash_queue.contains(&2));
+        assert!(hash_queue.contains(&3));
+    }
+
+    #[test]
+    fn should_delete_corrupt_front_when_dropping(){
+        let mut hash_queue = corrupt_front_setup(CorruptPolicy::SkipAndDelete);
+
+        assert_eq!(1, hash_queue.drop_front(1).unwrap());
+        assert_eq!(1, hash_queue.tree.len());
+        assert!(!hash_queue.contains(&2));
+        assert_eq!(Some(3), hash_queue.front().unwrap());
+    }
+
+    fn snapshot_options() -> HashQueueOptions {
+        HashQueueOptions {
+            snapshot_set: true,
+            ..HashQueueOptions::default()
+        }
+    }
+
+    /// Overwrites the snapshot on disk with one holding an extra element, which can only show up in the set if it was loaded from the snapshot.
+    fn tamper_with_snapshot(hash_queue: &HashQueue<u64>, extra: u64) {
+        let mut set = hash_queue.set.clone();
+        set.insert(extra);
+        let snapshot = hash_queue.snapshot.as_ref().unwrap();
+        snapshot.insert(SNAPSHOT_KEY, bincode::serialize(&(hash_queue.generation, &set, &hash_queue.duplicates)).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn should_load_set_from_snapshot_after_close(){
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path();
+        let mut hash_queue: HashQueue<u64> = HashQueue::open_with_options(path, "test", snapshot_options()).unwrap();
+        hash_queue.push_back(1).unwrap();
+        hash_queue.push_back(2).unwrap();
+        hash_queue.flush().unwrap();
+        tamper_with_snapshot(&hash_queue, 99);
+        hash_queue.close().unwrap();
+
+        let hash_queue: HashQueue<u64> = HashQueue::open_with_options(path, "test", snapshot_options()).unwrap();
+
+        assert!(hash_queue.set.contains(&1));
+        assert!(hash_queue.set.contains(&2));
+        assert!(hash_queue.set.contains(&99));
+    }
+
+    #[test]
+    fn should_ignore_stale_snapshot(){
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path();
+        let mut hash_queue: HashQueue<u64> = HashQueue::open_with_options(path, "test", snapshot_options()).unwrap();
+        hash_queue.push_back(1).unwrap();
+        hash_queue.flush().unwrap();
+        tamper_with_snapshot(&hash_queue, 99);
+
+        //Modify the queue, then close it without writing a new snapshot, as if the process had crashed.
+        hash_queue.push_back(2).unwrap();
+        hash_queue.snapshot = None;
+        hash_queue.close().unwrap();
+
+        let hash_queue: HashQueue<u64> = HashQueue::open_with_options(path, "test", snapshot_options()).unwrap();
+
+        assert!(!hash_queue.set.contains(&99));
+        assert!(hash_queue.snapshot_dirty);
+    }
+
+    #[test]
+    fn should_report_dense_queue_is_contiguous(){
+        let mut hash_queue = test_setup(1u64);
+
+        hash_queue.push_back(1).unwrap();
+        hash_queue.push_back(2).unwrap();
+        hash_queue.push_back(3).unwrap();
+
+        assert!(hash_queue.is_contiguous().unwrap());
+    }
+
+    #[test]
+    fn should_report_queue_with_hole_is_not_contiguous(){
+        let mut hash_queue = test_setup(1u64);
+
+        hash_queue.push_back(1).unwrap();
+        hash_queue.push_back(2).unwrap();
+        hash_queue.push_back(3).unwrap();
+        hash_queue.tree.remove(key_from_index(1)).unwrap();
+        hash_queue.set.remove(&2);
+
+        assert!(!hash_queue.is_contiguous().unwrap());
+    }
+
+    #[test]
+    fn should_report_empty_queue_is_contiguous(){
+        let hash_queue = test_setup(1u64);
+
+        assert!(hash_queue.is_contiguous().unwrap());
+    }
+
+    #[test]
+    fn should_transfer_items_to_another_queue(){
+        let mut source = test_setup(1u64);
+        let mut dest = test_setup(1u64);
+
+        source.push_back(1).unwrap();
+        source.push_back(2).unwrap();
+        source.push_back(3).unwrap();
+        dest.push_back(9).unwrap();
+
+        let moved = source.transfer_front_n(&mut dest, 2).unwrap();
+
+        assert_eq!(2, moved);
+        assert_eq!(Some(3), source.pop_front().unwrap());
+        assert!(source.is_empty());
+        assert_eq!(Some(9), dest.pop_front().unwrap());
+        assert_eq!(Some(1), dest.pop_front().unwrap());
+        assert_eq!(Some(2), dest.pop_front().unwrap());
+        assert!(dest.is_empty());
+    }
+
+    #[test]
+    fn should_skip_transferring_items_already_in_destination(){
+        let mut source = test_setup(1u64);
+        let mut dest = test_setup(1u64);
+
+        source.push_back(1).unwrap();
+        source.push_back(2).unwrap();
+        dest.push_back(1).unwrap();
+
+        let moved = source.transfer_front_n(&mut dest, 5).unwrap();
+
+        assert_eq!(1, moved);
+        assert!(source.is_empty());
+        assert_eq!(Some(1), dest.pop_front().unwrap());
+        assert_eq!(Some(2), dest.pop_front().unwrap());
+        assert!(dest.is_empty());
+    }
+
+    #[test]
+    fn should_keep_untransferred_items_when_destination_rejects_one(){
+        let mut source = test_setup(1u64);
+        let mut dest: HashQueue<u64> = test_setup_with(HashQueueOptions { capacity: Some(2), ..HashQueueOptions::default() });
+        source.extend_back(vec![1, 2, 3, 4]).unwrap();
+        dest.push_back(9).unwrap();
+
+        let result = source.transfer_front_n(&mut dest, 3);
+        assert!(matches!(result, Err(HashQueueError::CapacityExceeded { capacity: 2 })), "{:?}", result);
+
+        assert_eq!(vec![2, 3, 4], source.iter().collect::<Result<Vec<u64>, _>>().unwrap());
+        assert_eq!(vec![9, 1], dest.iter().collect::<Result<Vec<u64>, _>>().unwrap());
+        assert!(!source.contains(&1));
+        assert!(source.contains(&2));
+    }
+
+    #[test]
+    fn should_carry_delays_and_expiries_to_destination(){
+        let mut source = test_setup(1u64);
+        let mut dest = test_setup(1u64);
+        source.requeue_delayed(1, Duration::from_secs(60)).unwrap();
+        source.push_back_with_ttl(2, Duration::ZERO).unwrap();
+        source.push_back(3).unwrap();
+
+        assert_eq!(3, source.transfer_front_n(&mut dest, 3).unwrap());
+
+        assert!(source.is_empty());
+        assert_eq!(0, source.delays.as_ref().unwrap().len());
+        assert_eq!(0, source.expiries.as_ref().unwrap().len());
+        assert_eq!(Some(3), dest.pop_front().unwrap());
+        assert_eq!(None, dest.pop_front().unwrap());
+        assert_eq!(vec![1], dest.iter().collect::<Result<Vec<u64>, _>>().unwrap());
+    }
+
+    #[test]
+    fn should_adopt_existing_tree(){
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = db.open_tree("adopted").unwrap();
+        tree.insert(key_from_index(0), bincode::serialize(&7u64).unwrap()).unwrap();
+        tree.insert(key_from_index(1), bincode::serialize(&8u64).unwrap()).unwrap();
+        tree.insert(key_from_index(2), bincode::serialize(&9u64).unwrap()).unwrap();
+
+        let mut hash_queue: HashQueue<u64> = HashQueue::from_tree(tree).unwrap();
+
+        assert_eq!("adopted", hash_queue.name());
+        assert!(hash_queue.set.contains(&7));
+        assert!(hash_queue.set.contains(&8));
+        assert!(hash_queue.set.contains(&9));
+        assert!(!hash_queue.push_back(8).unwrap());
+        assert_eq!(Some(7), hash_queue.pop_front().unwrap());
+        assert_eq!(Some(8), hash_queue.pop_front().unwrap());
+        assert_eq!(Some(9), hash_queue.pop_front().unwrap());
+        assert!(hash_queue.is_empty());
+    }
+
+    #[test]
+    fn should_fail_to_adopt_tree_with_invalid_key(){
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = db.open_tree("adopted").unwrap();
+        tree.insert(b"not an index", bincode::serialize(&7u64).unwrap()).unwrap();
+
+        let result = HashQueue::<u64>::from_tree(tree);
+
+        assert!(matches!(result, Err(HashQueueError::InvalidKey { .. })));
+    }
+
+    #[test]
+    fn should_report_serialized_byte_size(){
+        let mut hash_queue = test_setup("a".to_string());
+        assert_eq!(0, hash_queue.byte_size().unwrap());
+
+        hash_queue.push_back("abc".to_string()).unwrap();
+        hash_queue.push_back("defgh".to_string()).unwrap();
+
+        //Each string is serialized as a u64 length followed by its bytes.
+        assert_eq!((8 + 3) + (8 + 5), hash_queue.byte_size().unwrap());
+    }
+
+    #[test]
+    fn should_cache_front_until_mutated(){
+        let mut hash_queue = test_setup(1u64);
+        hash_queue.push_back(1).unwrap();
+        hash_queue.push_back(2).unwrap();
+
+        let first = hash_queue.front_cached().unwrap().unwrap();
+        let second = hash_queue.front_cached().unwrap().unwrap();
+        assert_eq!(1, *first);
+        assert!(Arc::ptr_eq(&first, &second));
+
+        hash_queue.pop_front().unwrap();
+        let third = hash_queue.front_cached().unwrap().unwrap();
+        assert_eq!(2, *third);
+        assert!(!Arc::ptr_eq(&first, &third));
+
+        hash_queue.replace(&2, 3).unwrap();
+        assert_eq!(3, *hash_queue.front_cached().unwrap().unwrap());
+
+        hash_queue.pop_front().unwrap();
+        assert_eq!(None, hash_queue.front_cached().unwrap());
+    }
+
+    #[test]
+    fn should_drain_framed_items_to_writer(){
+        let mut hash_queue = test_setup("a".to_string());
+        hash_queue.push_back("a".to_string()).unwrap();
+        hash_queue.push_back("bc".to_string()).unwrap();
+        hash_queue.push_back("def".to_string()).unwrap();
+
+        let mut buffer: Vec<u8> = Vec::new();
+        let drained = hash_queue.drain_to_writer(&mut buffer).unwrap();
+
+        assert_eq!(3, drained);
+        assert!(hash_queue.is_empty());
+        assert_eq!(None, hash_queue.front().unwrap());
+
+        let mut items: Vec<String> = Vec::new();
+        let mut remaining = buffer.as_slice();
+        while !remaining.is_empty() {
+            let (length, rest) = remaining.split_at(8);
+            let length = u64::from_be_bytes(length.try_into().unwrap()) as usize;
+            let (frame, rest) = rest.split_at(length);
+            items.push(bincode::deserialize(frame).unwrap());
+            remaining = rest;
+        }
+        assert_eq!(vec!["a".to_string(), "bc".to_string(), "def".to_string()], items);
+    }
+
+    #[test]
+    fn should_round_trip_items_through_writer_and_reader(){
+        let mut source = test_setup(1u64);
+        let mut dest = test_setup(1u64);
+        source.push_back(1).unwrap();
+        source.push_back(2).unwrap();
+        source.push_back(3).unwrap();
+        dest.push_back(2).unwrap();
+
+        let mut buffer: Vec<u8> = Vec::new();
+        source.drain_to_writer(&mut buffer).unwrap();
+        let loaded = dest.load_from_reader(buffer.as_slice()).unwrap();
+
+        assert_eq!(2, loaded);
+        assert_eq!(Some(2), dest.pop_front().unwrap());
+        assert_eq!(Some(1), dest.pop_front().unwrap());
+        assert_eq!(Some(3), dest.pop_front().unwrap());
+        assert!(dest.is_empty());
+    }
+
+    #[test]
+    fn should_fail_to_load_truncated_frame(){
+        let mut source = test_setup(1u64);
+        let mut dest = test_setup(1u64);
+        source.push_back(1).unwrap();
+        source.push_back(2).unwrap();
+
+        let mut buffer: Vec<u8> = Vec::new();
+        source.drain_to_writer(&mut buffer).unwrap();
+        buffer.pop();
+        let result = dest.load_from_reader(buffer.as_slice());
+
+        assert!(matches!(result, Err(HashQueueError::TruncatedFrame { frame: 1 })));
+        assert_eq!(Some(1), dest.pop_front().unwrap());
+        assert!(dest.is_empty());
+    }
+
+    #[test]
+    fn should_keep_keys_sorted_when_mixing_operations_at_both_ends(){
+        let mut hash_queue = test_setup(1u64);
+
+        hash_queue.push_back(1).unwrap();
+        hash_queue.push_back(2).unwrap();
+        hash_queue.pop_back().unwrap();
+        hash_queue.push_back(3).unwrap();
+        hash_queue.pop_front().unwrap();
+        hash_queue.push_back(4).unwrap();
+        hash_queue.push_back(5).unwrap();
+        hash_queue.pop_back().unwrap();
+        hash_queue.push_back(6).unwrap();
+
+        hash_queue.assert_keys_sorted();
+        assert_eq!(Some(3), hash_queue.pop_front().unwrap());
+        assert_eq!(Some(4), hash_queue.pop_front().unwrap());
+        assert_eq!(Some(6), hash_queue.pop_front().unwrap());
+    }
+
+    #[test]
+    fn should_hide_requeued_item_until_delay_elapses(){
+        let mut hash_queue = test_setup(1u64);
+        hash_queue.push_back(1).unwrap();
+        hash_queue.requeue_delayed(2, Duration::from_millis(200)).unwrap();
+        hash_queue.push_back(3).unwrap();
+
+        assert_eq!(Some(1), hash_queue.pop_front().unwrap());
+        assert_eq!(Some(3), hash_queue.front().unwrap());
+        assert_eq!(Some(3), hash_queue.pop_front().unwrap());
+        assert_eq!(None, hash_queue.pop_front().unwrap());
+        assert!(!hash_queue.is_empty());
+
+        std::thread::sleep(Duration::from_millis(250));
+
+        assert_eq!(Some(2), hash_queue.front().unwrap());
+        assert_eq!(Some(2), hash_queue.pop_front().unwrap());
+        assert!(hash_queue.is_empty());
+    }
+
+    #[test]
+    fn should_not_delay_item_pushed_at_reused_index(){
+        let mut hash_queue = test_setup(1u64);
+        hash_queue.requeue_delayed(1, Duration::from_secs(60)).unwrap();
+        assert_eq!(Some(1), hash_queue.pop_back().unwrap());
+
+        hash_queue.push_back(2).unwrap();
+
+        assert_eq!(Some(2), hash_queue.pop_front().unwrap());
+    }
+
+    #[test]
+    fn should_report_healthy_queue(){
+        let mut hash_queue = test_setup(1u64);
+        hash_queue.push_back(1).unwrap();
+        hash_queue.push_back(2).unwrap();
+
+        let status = hash_queue.health_check().unwrap();
+
+        assert!(status.reachable && status.consistent && !status.poisoned);
+    }
+
+    #[test]
+    fn should_report_out_of_sync_queue_as_poisoned(){
+        let mut hash_queue = test_setup(1u64);
+        hash_queue.push_back(1).unwrap();
+        hash_queue.push_back(2).unwrap();
+        hash_queue.set.remove(&2);
+
+        assert!(!hash_queue.health_check().unwrap().consistent);
+        assert!(matches!(hash_queue.pop_back(), Err(HashQueueError::SyncError { .. })));
+
+        let status = hash_queue.health_check().unwrap();
+
+        assert!(status.reachable);
+        assert!(status.poisoned);
+    }
+
+    #[test]
+    fn should_rebuild_queue_from_raw_pairs(){
+        let mut hash_queue = test_setup(1u64);
+        hash_queue.push_back(1).unwrap();
+        hash_queue.push_back(2).unwrap();
+        hash_queue.push_back(3).unwrap();
+        hash_queue.pop_front().unwrap();
+
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = db.open_tree("copy").unwrap();
+        for pair in hash_queue.iter_raw() {
+            let (index, val) = pair.unwrap();
+            tree.insert(key_from_index(index), val).unwrap();
+        }
+        let mut copy: HashQueue<u64> = HashQueue::from_tree(tree).unwrap();
+
+        assert_eq!(hash_queue.set, copy.set);
+        assert_eq!(vec![1, 2], copy.iter_raw().map(|pair| pair.unwrap().0).collect::<Vec<i64>>());
+        assert_eq!(Some(2), copy.pop_front().unwrap());
+        assert_eq!(Some(3), copy.pop_front().unwrap());
+        assert!(copy.is_empty());
+    }
+
+    #[test]
+    fn should_copy_queue_through_raw_pairs(){
+        let mut hash_queue = test_setup(1u64);
+        let mut copy = test_setup(1u64);
+        hash_queue.push_back(1).unwrap();
+        hash_queue.push_back(2).unwrap();
+        hash_queue.push_back(3).unwrap();
+
+        let pairs = hash_queue.iter_raw().map(|pair| pair.map(|(index, val)| (index, val.to_vec()))).collect::<Result<Vec<(i64, Vec<u8>)>, HashQueueError>>().unwrap();
+        copy.apply_raw(pairs.into_iter()).unwrap();
+
+        assert_eq!(hash_queue.set, copy.set);
+        assert_eq!(Some(1), copy.pop_front().unwrap());
+        assert_eq!(Some(2), copy.pop_front().unwrap());
+        assert_eq!(Some(3), copy.pop_front().unwrap());
+        assert!(copy.is_empty());
+    }
+
+    #[test]
+    fn should_not_apply_raw_pairs_that_fail_to_deserialize(){
+        let mut hash_queue = test_setup(1u64);
+
+        let result = hash_queue.apply_raw(vec![(0, bincode::serialize(&1u64).unwrap()), (1, vec![1, 2])].into_iter());
+
+        assert!(matches!(result, Err(HashQueueError::BinCodeError { .. })));
+        assert!(hash_queue.tree.is_empty());
+        assert!(hash_queue.is_empty());
+    }
+
+    #[test]
+    fn should_reject_element_over_size_limit(){
+        let options = HashQueueOptions {
+            max_element_bytes: Some(12),
+            ..HashQueueOptions::default()
+        };
+        let mut hash_queue: HashQueue<String> = test_setup_with(options);
+
+        assert!(hash_queue.push_back("abcd".to_string()).unwrap());
+        let result = hash_queue.push_back("abcde".to_string());
+
+        assert!(matches!(result, Err(HashQueueError::ElementTooLarge { size: 13, max: 12 })));
+        assert!(!hash_queue.set.contains("abcde"));
+        assert_eq!(Some("abcd".to_string()), hash_queue.back().unwrap());
+        assert_eq!(Some("abcd".to_string()), hash_queue.pop_front().unwrap());
+        assert!(hash_queue.is_empty());
+    }
+
+    #[test]
+    fn should_rotate_items_from_front_to_back(){
+        let mut hash_queue = test_setup(1u64);
+        for i in 1..=4u64 {
+            hash_queue.push_back(i).unwrap();
+        }
+
+        assert_eq!(vec![1, 2], hash_queue.rotate_n(2).unwrap());
+
+        let result = hash_queue.fold(Vec::new(), |mut acc, item| { acc.push(item); acc }).unwrap();
+        assert_eq!(vec![3, 4, 1, 2], result);
+        hash_queue.assert_keys_sorted();
+    }
+
+    #[test]
+    fn should_not_reorder_when_rotating_none_or_all(){
+        let mut hash_queue = test_setup(1u64);
+        for i in 1..=3u64 {
+            hash_queue.push_back(i).unwrap();
+        }
+
+        assert!(hash_queue.rotate_n(0).unwrap().is_empty());
+        assert_eq!(vec![1, 2, 3], hash_queue.rotate_n(5).unwrap());
+
+        let result = hash_queue.fold(Vec::new(), |mut acc, item| { acc.push(item); acc }).unwrap();
+        assert_eq!(vec![1, 2, 3], result);
+        assert_eq!(3, hash_queue.peek_next_back_index().unwrap());
+    }
+
+    #[test]
+    fn should_approximate_length_of_contiguous_queue(){
+        let mut hash_queue = test_setup(1u64);
+
+        assert_eq!(0, hash_queue.len_approx().unwrap());
+        for i in 1..=4u64 {
+            hash_queue.push_back(i).unwrap();
+        }
+        hash_queue.pop_front().unwrap();
+
+        assert_eq!(hash_queue.tree.len(), hash_queue.len_approx().unwrap());
+    }
+
+    #[test]
+    fn should_overestimate_length_of_queue_with_hole(){
+        let mut hash_queue = test_setup(1u64);
+        for i in 1..=4u64 {
+            hash_queue.push_back(i).unwrap();
+        }
+        hash_queue.tree.remove(key_from_index(1)).unwrap();
+        hash_queue.set.remove(&2);
+
+        assert_eq!(3, hash_queue.tree.len());
+        assert_eq!(4, hash_queue.len_approx().unwrap());
+    }
+
+    #[test]
+    fn should_clear_when_predicate_holds(){
+        let mut hash_queue = test_setup(1u64);
+        hash_queue.push_back(1).unwrap();
+        hash_queue.push_back(2).unwrap();
+
+        let result = hash_queue.clear_if(|queue| queue.fold(true, |acc, item| acc && item < 10).unwrap()).unwrap();
+
+        assert!(result);
+        assert!(hash_queue.is_empty());
+        assert_eq!(None, hash_queue.front().unwrap());
+    }
+
+    #[test]
+    fn should_not_clear_when_predicate_fails(){
+        let mut hash_queue = test_setup(1u64);
+        hash_queue.push_back(1).unwrap();
+        hash_queue.push_back(20).unwrap();
+
+        let result = hash_queue.clear_if(|queue| queue.fold(true, |acc, item| acc && item < 10).unwrap()).unwrap();
+
+        assert!(!result);
+        assert_eq!(Some(1), hash_queue.pop_front().unwrap());
+        assert_eq!(Some(20), hash_queue.pop_front().unwrap());
+    }
+
+    #[test]
+    fn should_report_first_and_last_keys(){
+        let mut hash_queue = test_setup(1u64);
+
+        assert_eq!(None, hash_queue.first_key().unwrap());
+        assert_eq!(None, hash_queue.last_key().unwrap());
+
+        for i in 1..=4u64 {
+            hash_queue.push_back(i).unwrap();
+        }
+        assert_eq!(Some(0), hash_queue.first_key().unwrap());
+        assert_eq!(Some(3), hash_queue.last_key().unwrap());
+
+        hash_queue.pop_front().unwrap();
+        hash_queue.pop_back().unwrap();
+        assert_eq!(Some(1), hash_queue.first_key().unwrap());
+        assert_eq!(Some(2), hash_queue.last_key().unwrap());
+    }
+
+    #[test]
+    fn should_force_push_duplicate_item(){
+        let mut hash_queue = test_setup(1u64);
+        hash_queue.push_back(1).unwrap();
+        hash_queue.push_back(2).unwrap();
+
+        assert_eq!(2, hash_queue.push_back_force(1).unwrap());
+        assert_eq!(3, hash_queue.tree.len());
+        assert!(hash_queue.health_check().unwrap().consistent);
+
+        assert_eq!(Some(1), hash_queue.pop_front().unwrap());
+        assert!(!hash_queue.push_back(1).unwrap());
+        assert_eq!(Some(2), hash_queue.pop_front().unwrap());
+        assert_eq!(Some(1), hash_queue.pop_front().unwrap());
+        assert!(hash_queue.is_empty());
+    }
+
+    #[test]
+    fn should_rewind_to_checkpoint(){
+        let mut hash_queue = test_setup(1u64);
+        hash_queue.push_back(1).unwrap();
+        hash_queue.push_back(2).unwrap();
+
+        let checkpoint = hash_queue.checkpoint().unwrap();
+        hash_queue.push_back(3).unwrap();
+        hash_queue.push_back(4).unwrap();
+        let later = hash_queue.checkpoint().unwrap();
+        hash_queue.push_back(5).unwrap();
+
+        assert_eq!(3, hash_queue.rewind_to(checkpoint).unwrap());
+
+        let result = hash_queue.fold(Vec::new(), |mut acc, item| { acc.push(item); acc }).unwrap();
+        assert_eq!(vec![1, 2], result);
+        assert!(hash_queue.push_back(3).unwrap());
+        assert!(matches!(hash_queue.rewind_to(later), Err(HashQueueError::UnknownCheckpoint { .. })));
+    }
+
+    #[test]
+    fn should_leave_queue_and_checkpoints_untouched_when_rewinding_over_corrupt_element(){
+        let mut hash_queue = test_setup(1u64);
+        hash_queue.push_back(1).unwrap();
+        let checkpoint = hash_queue.checkpoint().unwrap();
+        hash_queue.push_back(2).unwrap();
+        hash_queue.tree.insert(key_from_index(100), vec![1u8, 2, 3]).unwrap();
+        let later = hash_queue.checkpoint().unwrap();
+
+        let result = hash_queue.rewind_to(checkpoint);
+        assert!(matches!(result, Err(HashQueueError::BinCodeError { .. })), "{:?}", result);
+
+        assert_eq!(3, hash_queue.tree.len());
+        assert!(hash_queue.contains(&2));
+        assert_eq!(0, hash_queue.rewind_to(later).unwrap());
+        hash_queue.tree.remove(key_from_index(100)).unwrap();
+        assert_eq!(1, hash_queue.rewind_to(checkpoint).unwrap());
+        assert!(!hash_queue.contains(&2));
+    }
+
+    #[test]
+    fn should_prune_orphaned_set_entries(){
+        let mut hash_queue = test_setup(1u64);
+        hash_queue.push_back(1).unwrap();
+        hash_queue.push_back(2).unwrap();
+        hash_queue.set.insert(3);
+
+        assert!(!hash_queue.health_check().unwrap().consistent);
+        assert_eq!(1, hash_queue.reconcile().unwrap());
+
+        assert!(!hash_queue.set.contains(&3));
+        assert!(hash_queue.health_check().unwrap().consistent);
+        assert!(hash_queue.push_back(3).unwrap());
+    }
+
+    #[test]
+    fn should_read_queue_opened_with_eager_load(){
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path();
+        let mut hash_queue = test_setup_at(1u64, path);
+        for i in 1..=3u64 {
+            hash_queue.push_back(i).unwrap();
+        }
+        hash_queue.close().unwrap();
+
+        let options = HashQueueOptions {
+            eager_load: true,
+            ..HashQueueOptions::default()
+        };
+        let hash_queue: HashQueue<u64> = HashQueue::open_with_options(path, "test", options).unwrap();
+
+        assert_eq!(Some(1), hash_queue.front().unwrap());
+        assert_eq!(Some(3), hash_queue.back().unwrap());
+        let result = hash_queue.fold(Vec::new(), |mut acc, item| { acc.push(item); acc }).unwrap();
+        assert_eq!(vec![1, 2, 3], result);
+    }
+
+    #[test]
+    fn should_only_iterate_items_present_when_called(){
+        let mut hash_queue = test_setup(1u64);
+        for i in 1..=4u64 {
+            hash_queue.push_back(i).unwrap();
+        }
+
+        let iter = hash_queue.stable_iter().unwrap();
+        let tree = hash_queue.tree.clone();
+        std::thread::spawn(move || {
+            let mut other: HashQueue<u64> = HashQueue::from_tree(tree).unwrap();
+            other.pop_front().unwrap();
+            other.push_back(5).unwrap();
+            other.push_back(6).unwrap();
+        }).join().unwrap();
+
+        let result = iter.collect::<Result<Vec<u64>, _>>().unwrap();
+        assert_eq!(vec![2, 3, 4], result);
+    }
+
+    #[test]
+    fn should_iterate_a_consistent_snapshot_of_a_shared_queue(){
+        let mut hash_queue = test_setup(1u64);
+        hash_queue.extend_back((0..100).collect::<Vec<u64>>()).unwrap();
+        let shared = Arc::new(std::sync::RwLock::new(hash_queue));
+
+        let writer = {
+            let shared = shared.clone();
+            std::thread::spawn(move || {
+                for i in 100..300u64 {
+                    let mut hash_queue = shared.write().unwrap();
+                    hash_queue.pop_front().unwrap();
+                    hash_queue.push_back(i).unwrap();
+                }
+            })
+        };
+        while !writer.is_finished() {
+            let hash_queue = shared.read().unwrap();
+            let result = hash_queue.stable_iter().unwrap().collect::<Result<Vec<u64>, _>>().unwrap();
+            //Every write pops one element and pushes the next, so any snapshot is a window of 100 consecutive elements.
+            assert_eq!((result[0]..result[0] + 100).collect::<Vec<u64>>(), result);
+        }
+        writer.join().unwrap();
+
+        let hash_queue = shared.read().unwrap();
+        assert_eq!((200..300).collect::<Vec<u64>>(), hash_queue.stable_iter().unwrap().collect::<Result<Vec<u64>, _>>().unwrap());
+    }
+
+    fn corrupt_front_setup(corrupt_policy: CorruptPolicy) -> HashQueue<u64> {
+        let options = HashQueueOptions {
+            corrupt_policy,
+            ..HashQueueOptions::default()
+        };
+        let mut hash_queue = test_setup_with(options);
+        hash_queue.tree.insert(key_from_index(0), vec![1u8, 2, 3]).unwrap();
+        hash_queue.push_back(2).unwrap();
+        hash_queue.push_back(3).unwrap();
+        hash_queue
+    }
+
+    #[test]
+    fn should_leave_corrupt_front_in_place_by_default(){
+        let mut hash_queue = corrupt_front_setup(CorruptPolicy::Fail);
+
+        assert!(matches!(hash_queue.pop_front(), Err(HashQueueError::BinCodeError { .. })));
+        assert!(matches!(hash_queue.pop_front(), Err(HashQueueError::BinCodeError { .. })));
+        assert_eq!(3, hash_queue.tree.len());
+    }
+
+    #[test]
+    fn should_delete_corrupt_front(){
+        let mut hash_queue = corrupt_front_setup(CorruptPolicy::SkipAndDelete);
+
+        assert_eq!(Some(2), hash_queue.pop_front().unwrap());
+        assert_eq!(1, hash_queue.tree.len());
+        assert_eq!(Some(3), hash_queue.pop_front().unwrap());
+    }
+
+    #[test]
+    fn should_quarantine_corrupt_front(){
+        let mut hash_queue = corrupt_front_setup(CorruptPolicy::SkipAndQuarantine);
+
+        assert_eq!(Some(2), hash_queue.pop_front().unwrap());
+        assert_eq!(1, hash_queue.tree.len());
+
+        let quarantine = hash_queue.db.as_ref().unwrap().open_tree(b"test::quarantine").unwrap();
+        let quarantined = quarantine.iter().values().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(vec![vec![1u8, 2, 3]], quarantined.iter().map(|val| val.to_vec()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn should_find_item_after_value(){
+        let mut hash_queue = test_setup(1u64);
+        for i in 1..=3u64 {
+            hash_queue.push_back(i).unwrap();
+        }
+
+        assert_eq!(Some(3), hash_queue.next_after(&2).unwrap());
+        assert_eq!(None, hash_queue.next_after(&3).unwrap());
+        assert_eq!(None, hash_queue.next_after(&4).unwrap());
+    }
+
+    #[test]
+    fn should_find_item_before_value(){
+        let mut hash_queue = test_setup(1u64);
+        for i in 1..=3u64 {
+            hash_queue.push_back(i).unwrap();
+        }
+
+        assert_eq!(Some(1), hash_queue.prev_before(&2).unwrap());
+        assert_eq!(None, hash_queue.prev_before(&1).unwrap());
+        assert_eq!(None, hash_queue.prev_before(&4).unwrap());
+    }
+
+    #[test]
+    fn should_pop_front_into_tree_once(){
+        let mut hash_queue = test_setup(1u64);
+        let dest = hash_queue.db.as_ref().unwrap().open_tree("processed").unwrap();
+        dest.clear().unwrap();
+        hash_queue.push_back(1).unwrap();
+        hash_queue.push_back(2).unwrap();
+
+        assert_eq!(Some(1), hash_queue.pop_front_into_tree(&dest).unwrap());
+        assert_eq!(Some(2), hash_queue.front().unwrap());
+        assert!(!hash_queue.set.contains(&1));
+        assert_eq!(1, dest.len());
+
+        assert_eq!(Some(2), hash_queue.pop_front_into_tree(&dest).unwrap());
+        assert_eq!(None, hash_queue.pop_front_into_tree(&dest).unwrap());
+
+        let moved = dest.iter().values().map(|val| bincode::deserialize::<u64>(&val.unwrap()).unwrap()).collect::<Vec<_>>();
+        assert_eq!(vec![1, 2], moved);
+        assert!(hash_queue.is_empty());
+    }
+
+    #[test]
+    fn should_rebuild_set_from_named_tree_on_reopen(){
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path();
+        let mut hash_queue = test_setup_at(1u64, path);
+        for i in 1..=3u64 {
+            hash_queue.push_back(i).unwrap();
+        }
+        hash_queue.close().unwrap();
+
+        let mut hash_queue: HashQueue<u64> = HashQueue::open(path, "test").unwrap();
+
+        assert!(!hash_queue.is_empty());
+        for i in 1..=3u64 {
+            assert!(hash_queue.set.contains(&i));
+        }
+        assert!(!hash_queue.push_back(2).unwrap());
+        assert_eq!(Some(1), hash_queue.pop_front().unwrap());
+    }
+
+    #[test]
+    fn should_report_length_of_queue(){
+        let mut hash_queue = test_setup(1u64);
+        assert_eq!(0, hash_queue.len());
+
+        for i in 1..=5u64 {
+            hash_queue.push_back(i).unwrap();
+        }
+        assert_eq!(5, hash_queue.len());
+
+        hash_queue.pop_front().unwrap();
+        hash_queue.pop_back().unwrap();
+        assert_eq!(3, hash_queue.len());
+
+        hash_queue.push_back_force(2).unwrap();
+        assert_eq!(4, hash_queue.len());
+    }
+
+    #[test]
+    fn should_push_to_both_ends(){
+        let mut hash_queue = test_setup(1u64);
+
+        assert!(hash_queue.push_front(3).unwrap());
+        assert!(hash_queue.push_back(4).unwrap());
+        assert!(hash_queue.push_front(2).unwrap());
+        assert!(hash_queue.push_front(1).unwrap());
+        assert!(hash_queue.push_back(5).unwrap());
+        assert!(!hash_queue.push_front(4).unwrap());
+        hash_queue.assert_keys_sorted();
+
+        let result = hash_queue.fold(Vec::new(), |mut acc, item| { acc.push(item); acc }).unwrap();
+        assert_eq!(vec![1, 2, 3, 4, 5], result);
+        assert_eq!(Some(-2), hash_queue.first_key().unwrap());
+
+        assert_eq!(Some(1), hash_queue.pop_front().unwrap());
+        assert_eq!(Some(5), hash_queue.pop_back().unwrap());
+        assert_eq!(Some(2), hash_queue.pop_front().unwrap());
+        assert_eq!(Some(4), hash_queue.pop_back().unwrap());
+        assert_eq!(Some(3), hash_queue.pop_front().unwrap());
+        assert!(hash_queue.is_empty());
+    }
+
+    #[test]
+    fn should_keep_order_of_queue_written_with_plain_keys(){
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = db.open_tree("legacy").unwrap();
+        for (index, value) in [(0i64, 7u64), (1, 8), (2, 9)] {
+            tree.insert(index.to_be_bytes(), bincode::serialize(&value).unwrap()).unwrap();
+        }
+
+        let mut hash_queue: HashQueue<u64> = HashQueue::from_tree(tree.clone()).unwrap();
+        hash_queue.push_back(10).unwrap();
+
+        assert!(hash_queue.push_front(6).unwrap());
+        let result = hash_queue.fold(Vec::new(), |mut acc, item| { acc.push(item); acc }).unwrap();
+        assert_eq!(vec![6, 7, 8, 9, 10], result);
+        assert_eq!(Some(key_from_index(-1).to_vec()), tree.first().unwrap().map(|(key, _val)| key.to_vec()));
+        assert_eq!(Some(key_from_index(3).to_vec()), tree.last().unwrap().map(|(key, _val)| key.to_vec()));
+    }
+
+    #[test]
+    fn should_migrate_sidecars_of_queue_written_with_plain_keys(){
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = db.open_tree("test").unwrap();
+        for (index, value) in [(0i64, 7u64), (1, 8), (2, 9)] {
+            tree.insert(index.to_be_bytes(), bincode::serialize(&value).unwrap()).unwrap();
+        }
+        let delays = db.open_tree("test::delays").unwrap();
+        delays.insert(0i64.to_be_bytes(), &u64::MAX.to_be_bytes()).unwrap();
+        let checkpoints = db.open_tree("test::checkpoints").unwrap();
+        checkpoints.insert(5u64.to_be_bytes(), &2i64.to_be_bytes()).unwrap();
+
+        let mut hash_queue: HashQueue<u64> = HashQueue::open_db(db, None, "test", HashQueueOptions::default()).unwrap();
+
+        assert_eq!(Some(8), hash_queue.front().unwrap());
+        assert_eq!(1, hash_queue.rewind_to(5).unwrap());
+        assert!(!hash_queue.contains(&9));
+        assert!(hash_queue.push_front(6).unwrap());
+        assert_eq!(Some(key_from_index(0).to_vec()), delays.first().unwrap().map(|(key, _val)| key.to_vec()));
+    }
+
+    #[test]
+    fn should_report_whether_queue_contains_item(){
+        let mut hash_queue = test_setup("a".to_string());
+        hash_queue.push_back("a".to_string()).unwrap();
+
+        assert!(hash_queue.contains(&"a".to_string()));
+        assert!(!hash_queue.contains(&"b".to_string()));
+
+        hash_queue.pop_front().unwrap();
+        assert!(!hash_queue.contains(&"a".to_string()));
+    }
+
+    #[test]
+    fn should_box_error_as_std_error(){
+        let error: Box<dyn std::error::Error> = Box::new(HashQueueError::IndexOverflow { index: 3 });
+        assert_eq!("No index is left to push to past 3", error.to_string());
+        assert!(error.source().is_none());
+
+        let hash_queue = test_setup(1u64);
+        hash_queue.tree.insert(key_from_index(0), vec![1u8]).unwrap();
+        let error: Box<dyn std::error::Error> = Box::new(hash_queue.front().unwrap_err());
+        assert!(error.to_string().starts_with("Failed to deserialize data"));
+        assert!(error.source().is_some());
+    }
+
+    #[test]
+    fn should_clear_all_items(){
+        let mut hash_queue = test_setup(1u64);
+        for i in 1..=3u64 {
+            hash_queue.push_back(i).unwrap();
+        }
+
+        hash_queue.clear().unwrap();
+
+        assert!(hash_queue.is_empty());
+        assert_eq!(0, hash_queue.tree.len());
+        assert_eq!(None, hash_queue.front().unwrap());
+        assert!(hash_queue.push_back(1).unwrap());
+    }
+
+    #[test]
+    fn should_iterate_items_in_order(){
+        let mut hash_queue = test_setup(1u64);
+        hash_queue.push_back(2).unwrap();
+        hash_queue.push_back(3).unwrap();
+        hash_queue.push_front(1).unwrap();
+
+        let result = hash_queue.iter().collect::<Result<Vec<u64>, _>>().unwrap();
+
+        assert_eq!(vec![1, 2, 3], result);
+        assert_eq!(3, hash_queue.len());
+    }
+
+    #[test]
+    fn should_report_desync_when_popping_untracked_item(){
+        let mut hash_queue = test_setup(1u64);
+        hash_queue.push_back(2).unwrap();
+        hash_queue.tree.insert(key_from_index(-1), bincode::serialize(&1u64).unwrap()).unwrap();
+        hash_queue.tree.insert(key_from_index(1), bincode::serialize(&3u64).unwrap()).unwrap();
+
+        assert!(matches!(hash_queue.pop_front(), Err(HashQueueError::SyncError { .. })));
+        assert!(matches!(hash_queue.pop_back(), Err(HashQueueError::SyncError { .. })));
+        assert!(hash_queue.health_check().unwrap().poisoned);
+    }
+
+    #[test]
+    fn should_reject_push_past_capacity(){
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path();
+        let mut hash_queue: HashQueue<u64> = HashQueue::open_bounded(path, "test", 2).unwrap();
+        hash_queue.push_back(1).unwrap();
+        hash_queue.push_back(2).unwrap();
+
+        assert!(matches!(hash_queue.push_back(3), Err(HashQueueError::CapacityExceeded { capacity: 2 })));
+        assert!(matches!(hash_queue.push_front(3), Err(HashQueueError::CapacityExceeded { capacity: 2 })));
+        assert!(!hash_queue.contains(&3));
+        assert_eq!(2, hash_queue.tree.len());
+
+        hash_queue.pop_front().unwrap();
+        assert!(hash_queue.push_back(3).unwrap());
+    }
+
+    #[test]
+    fn should_reject_duplicate_in_full_queue_as_duplicate(){
+        let dir = tempfile::tempdir().unwrap();
+        let mut hash_queue: HashQueue<u64> = HashQueue::open_bounded(dir.path(), "test", 2).unwrap();
+        hash_queue.push_back(1).unwrap();
+        hash_queue.push_back(2).unwrap();
+
+        assert!(!hash_queue.push_back(1).unwrap());
+        assert!(!hash_queue.push_front(2).unwrap());
+        assert_eq!(2, hash_queue.tree.len());
+        let result = hash_queue.push_back(3);
+        assert!(matches!(result, Err(HashQueueError::CapacityExceeded { capacity: 2 })), "{:?}", result);
+    }
+
+    #[test]
+    fn should_drain_all_items_in_order(){
+        let mut hash_queue = test_setup(1u64);
+        for i in 1..=3u64 {
+            hash_queue.push_back(i).unwrap();
+        }
+
+        assert_eq!(vec![1, 2, 3], hash_queue.drain().unwrap());
+
+        assert!(hash_queue.is_empty());
+        assert_eq!(0, hash_queue.tree.len());
+        assert!(hash_queue.drain().unwrap().is_empty());
+    }
+
+    #[test]
+    fn should_peek_nth_item(){
+        let mut hash_queue = test_setup(1u64);
+        for i in 1..=3u64 {
+            hash_queue.push_back(i).unwrap();
+        }
+
+        assert_eq!(hash_queue.front().unwrap(), hash_queue.peek_nth(0).unwrap());
+        assert_eq!(Some(2), hash_queue.peek_nth(1).unwrap());
+        assert_eq!(None, hash_queue.peek_nth(3).unwrap());
+        assert_eq!(3, hash_queue.len());
+    }
+
+    #[test]
+    fn should_peek_nth_item_from_the_back(){
+        let mut hash_queue = test_setup(1u64);
+        for i in 1..=3u64 {
+            hash_queue.push_back(i).unwrap();
+        }
+
+        assert_eq!(hash_queue.back().unwrap(), hash_queue.peek_nth_back(0).unwrap());
+        assert_eq!(Some(2), hash_queue.peek_nth_back(1).unwrap());
+        assert_eq!(None, hash_queue.peek_nth_back(3).unwrap());
+        assert_eq!(3, hash_queue.len());
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+    struct Job {
+        id: u64,
+        name: String,
+    }
+
+    #[test]
+    fn should_store_items_as_json(){
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path();
+        let options = HashQueueOptions {
+            codec: Codec::Json,
+            ..HashQueueOptions::default()
+        };
+        let mut hash_queue: HashQueue<Job> = HashQueue::open_with_options(path, "test", options).unwrap();
+        let job = Job { id: 1, name: "build".to_string() };
+        hash_queue.push_back(job.clone()).unwrap();
+
+        let (_key, val) = hash_queue.tree.first().unwrap().unwrap();
+        let stored = std::str::from_utf8(&val).unwrap();
+        assert_eq!(r#"{"id":1,"name":"build"}"#, stored);
+        assert!(serde_json::from_str::<serde_json::Value>(stored).is_ok());
+
+        hash_queue.close().unwrap();
+        let mut hash_queue: HashQueue<Job> = HashQueue::open_with_options(path, "test", HashQueueOptions { codec: Codec::Json, ..HashQueueOptions::default() }).unwrap();
+        assert!(hash_queue.contains(&job));
+        assert_eq!(Some(job), hash_queue.pop_front().unwrap());
+    }
+
+    #[test]
+    fn should_remove_item_from_middle(){
+        let mut hash_queue = test_setup(1u64);
+        for i in 1..=4u64 {
+            hash_queue.push_back(i).unwrap();
+        }
+
+        assert!(hash_queue.remove(&2).unwrap());
+        assert!(!hash_queue.remove(&2).unwrap());
+        assert!(!hash_queue.remove(&5).unwrap());
+
+        assert!(!hash_queue.contains(&2));
+        let result = hash_queue.iter().collect::<Result<Vec<u64>, _>>().unwrap();
+        assert_eq!(vec![1, 3, 4], result);
+        assert!(hash_queue.health_check().unwrap().consistent);
+    }
+
+    #[test]
+    fn should_pop_every_item_when_consumed(){
+        let mut hash_queue = test_setup(1u64);
+        for i in 1..=3u64 {
+            hash_queue.push_back(i).unwrap();
+        }
+        let tree = hash_queue.tree.clone();
+
+        let result = hash_queue.into_iter().collect::<Result<Vec<u64>, _>>().unwrap();
+
+        assert_eq!(vec![1, 2, 3], result);
+        assert!(tree.is_empty());
+    }
+
+    #[test]
+    fn should_extend_queue_skipping_duplicates(){
+        let mut hash_queue = test_setup(1u64);
+
+        assert_eq!(3, hash_queue.extend_back([1, 2, 2, 3]).unwrap());
+        assert_eq!(3, hash_queue.len());
+
+        hash_queue.extend([3, 4]);
+        let result = hash_queue.iter().collect::<Result<Vec<u64>, _>>().unwrap();
+        assert_eq!(vec![1, 2, 3, 4], result);
+    }
+
+    #[test]
+    fn should_match_autoflushed_queue_after_bulk_load(){
+        let mut autoflushed = test_setup(1u64);
+        let mut bulk = test_setup(1u64);
+
+        for i in 0..1000u64 {
+            autoflushed.push_back(i).unwrap();
+        }
+        bulk.set_autoflush(false);
+        for i in 0..1000u64 {
+            bulk.push_back(i).unwrap();
+        }
+        bulk.flush().unwrap();
+        bulk.set_autoflush(true);
+
+        assert_eq!(autoflushed.len(), bulk.len());
+        assert_eq!(autoflushed.set, bulk.set);
+        let expected = autoflushed.iter_raw().collect::<Result<Vec<_>, _>>().unwrap();
+        let result = bulk.iter_raw().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn should_receive_events_from_another_thread(){
+        let mut hash_queue = test_setup(1u64);
+        let mut subscription = hash_queue.subscribe();
+
+        let pusher = std::thread::spawn(move || {
+            hash_queue.push_back(7).unwrap();
+            hash_queue.pop_front().unwrap();
+        });
+
+        let pushed = subscription.next_timeout(Duration::from_secs(5)).unwrap().unwrap();
+        assert_eq!(QueueEvent::Pushed { index: 0, item: 7 }, pushed);
+        let popped = subscription.next_timeout(Duration::from_secs(5)).unwrap().unwrap();
+        assert_eq!(QueueEvent::Removed { index: 0 }, popped);
+        pusher.join().unwrap();
+    }
+
+    #[test]
+    fn should_push_batch_in_order(){
+        let mut hash_queue = test_setup(1u64);
+        hash_queue.push_back(1).unwrap();
+
+        assert_eq!(3, hash_queue.push_back_batch(vec![2, 3, 1, 3, 4]).unwrap());
+
+        assert_eq!(4, hash_queue.len());
+        let result = hash_queue.iter().collect::<Result<Vec<u64>, _>>().unwrap();
+        assert_eq!(vec![1, 2, 3, 4], result);
+        hash_queue.assert_keys_sorted();
+    }
+
+    //Serializes like a u64, except that 0 always fails to serialize.
+    #[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+    struct Unserializable(u64);
+
+    impl Serialize for Unserializable {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            if self.0 == 0 {
+                return Err(serde::ser::Error::custom("zero can't be serialized"));
+            }
+            serializer.serialize_u64(self.0)
+        }
+    }
+
+    #[test]
+    fn should_leave_queue_unchanged_when_batch_fails_to_serialize(){
+        let mut hash_queue = test_setup(Unserializable(1));
+        hash_queue.push_back(Unserializable(1)).unwrap();
+
+        let result = hash_queue.push_back_batch(vec![Unserializable(2), Unserializable(0), Unserializable(3)]);
+
+        assert!(matches!(result, Err(HashQueueError::BinCodeError { .. })));
+        assert_eq!(1, hash_queue.len());
+        assert_eq!(1, hash_queue.tree.len());
+        assert!(!hash_queue.contains(&Unserializable(2)));
+    }
+
+    #[test]
+    fn should_not_overwrite_pushes_from_another_handle(){
+        let mut hash_queue = test_setup(0u64);
+        let tree = hash_queue.tree.clone();
+        let other = std::thread::spawn(move || {
+            let mut other: HashQueue<u64> = HashQueue::from_tree(tree).unwrap();
+            for i in 1000..1500u64 {
+                other.push_back(i).unwrap();
+            }
+        });
+        for i in 0..500u64 {
+            hash_queue.push_back(i).unwrap();
+        }
+        other.join().unwrap();
+
+        let mut result = hash_queue.tree.iter().values()
+            .map(|val| bincode::deserialize::<u64>(&val.unwrap()).unwrap())
+            .collect::<Vec<u64>>();
+        result.sort();
+        assert_eq!((0..500).chain(1000..1500).collect::<Vec<u64>>(), result);
+    }
+
+    #[test]
+    fn should_round_trip_through_export_and_import(){
+        let mut hash_queue = test_setup(1u64);
+        hash_queue.push_back(1).unwrap();
+        hash_queue.push_back(2).unwrap();
+        hash_queue.push_back(3).unwrap();
+        let mut backup = Vec::new();
+        hash_queue.export(&mut backup).unwrap();
+
+        let mut restored = test_setup(1u64);
+        let count = restored.import(backup.as_slice()).unwrap();
+
+        assert_eq!(3, count);
+        assert!(restored.contains(&2));
+        assert!(!restored.push_back(3).unwrap());
+        let result = restored.into_iter().collect::<Result<Vec<u64>, _>>().unwrap();
+        assert_eq!(vec![1, 2, 3], result);
+    }
+
+    #[test]
+    fn should_retain_matching_items_in_order(){
+        let mut hash_queue = test_setup(1u64);
+        hash_queue.extend_back(vec![1, 2, 3, 4]).unwrap();
+
+        let removed = hash_queue.retain(|item| item % 2 == 0).unwrap();
+
+        assert_eq!(2, removed);
+        assert!(!hash_queue.contains(&1));
+        let result = hash_queue.into_iter().collect::<Result<Vec<u64>, _>>().unwrap();
+        assert_eq!(vec![2, 4], result);
+    }
+
+    #[test]
+    fn should_peek_both_ends(){
+        let mut hash_queue = test_setup(1u64);
+        assert_eq!((None, None), hash_queue.peek_both().unwrap());
+        hash_queue.extend_back(vec![1, 2, 3]).unwrap();
+
+        let result = hash_queue.peek_both().unwrap();
+
+        assert_eq!((Some(1), Some(3)), result);
+        assert_eq!(3, hash_queue.len());
     }
 
     #[test]
-    fn should_add_to_hash_queue(){
-        let mut hash_queue = test_setup("1".to_string(), "./tests/should_add_to_hash_queue");
-        let result = hash_queue.push_back("1".to_string());
-        assert_eq!(true, result.unwrap());
+    fn should_read_a_field_of_the_front_with_with_front(){
+        let mut hash_queue = test_setup(Job { id: 0, name: String::new() });
+        assert_eq!(None, hash_queue.with_front(|job| job.id).unwrap());
+        hash_queue.push_back(Job { id: 7, name: "first".to_string() }).unwrap();
+        hash_queue.push_back(Job { id: 8, name: "second".to_string() }).unwrap();
+
+        let result = hash_queue.with_front(|job| job.id).unwrap();
+
+        assert_eq!(Some(7), result);
+        assert_eq!(2, hash_queue.len());
     }
 
+    #[test]
+    fn should_count_rejected_duplicates(){
+        let mut hash_queue = test_setup(1u64);
+        for i in [1, 1, 2, 2, 2] {
+            hash_queue.push_back(i).unwrap();
+        }
+        assert_eq!(3, hash_queue.rejected_count());
+
+        hash_queue.push_back_batch(vec![2, 3, 3]).unwrap();
+
+        assert_eq!(5, hash_queue.rejected_count());
+    }
 
+    #[cfg(feature = "compression")]
     #[test]
-    fn should_report_hash_queue_is_empty(){
-        let hash_queue= test_setup("1".to_string(), "./tests/should_report_hash_queue_is_empty");
-        let result = hash_queue.is_empty();
-        assert_eq!(true, result);
+    fn should_round_trip_compressed_items(){
+        let options = HashQueueOptions { compress: true, ..HashQueueOptions::default() };
+        let mut hash_queue: HashQueue<String> = test_setup_with(options);
+        let item = "{\"status\": \"queued\"} ".repeat(1000);
+        hash_queue.push_back(item.clone()).unwrap();
+
+        let stored = hash_queue.tree.first().unwrap().unwrap().1;
+        assert!(stored.len() < item.len() / 10, "stored {} bytes for a {} byte item", stored.len(), item.len());
+        let result = hash_queue.pop_front().unwrap();
+
+        assert_eq!(Some(item), result);
+    }
+
+    #[cfg(feature = "compression")]
+    #[derive(Debug)]
+    struct Xor(u8);
+
+    #[cfg(feature = "compression")]
+    impl ByteCodec for Xor {
+        fn encode(&self, bytes: &[u8]) -> Result<Vec<u8>, HashQueueError> {
+            Ok(bytes.iter().map(|byte| byte ^ self.0).collect())
+        }
+
+        fn decode(&self, bytes: &[u8]) -> Result<Vec<u8>, HashQueueError> {
+            self.encode(bytes)
+        }
     }
 
+    #[derive(Debug)]
+    struct Tag(u8);
+
+    impl ByteCodec for Tag {
+        fn encode(&self, bytes: &[u8]) -> Result<Vec<u8>, HashQueueError> {
+            Ok([bytes, &[self.0]].concat())
+        }
+
+        fn decode(&self, bytes: &[u8]) -> Result<Vec<u8>, HashQueueError> {
+            match bytes.split_last() {
+                Some((&tag, rest)) if tag == self.0 => Ok(rest.to_vec()),
+                _ => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "missing tag").into()),
+            }
+        }
+    }
 
     #[test]
-    fn should_report_hash_queue_is_not_empty(){
-        let mut hash_queue= test_setup("1".to_string(), "./tests/should_report_hash_queue_is_not_empty");
-        hash_queue.push_back("1".to_string()).unwrap();
-        let result = hash_queue.is_empty();
-        assert_eq!(false, result);
+    fn should_apply_composed_byte_codecs_in_order(){
+        let options = HashQueueOptions {
+            codec: Codec::Json,
+            byte_codec: Some(Arc::new(CompositeCodec::new(Tag(1), Tag(2)))),
+            ..HashQueueOptions::default()
+        };
+        let mut hash_queue: HashQueue<u64> = test_setup_with(options);
+        hash_queue.push_back(7).unwrap();
+
+        let stored = hash_queue.tree.first().unwrap().unwrap().1;
+        assert_eq!(b"7\x01\x02".to_vec(), stored.to_vec());
+        assert_eq!(Some(7), hash_queue.pop_front().unwrap());
     }
 
+    #[cfg(feature = "compression")]
     #[test]
-    fn should_see_front_of_hash_queue_and_dequeue(){
-        let mut hash_queue= test_setup(1u64, "./tests/should_see_front_of_hash_queue_and_dequeue");
+    fn should_round_trip_items_compressed_then_encrypted(){
+        let options = HashQueueOptions {
+            codec: Codec::Json,
+            byte_codec: Some(Arc::new(CompositeCodec::new(Deflate, Xor(0x5a)))),
+            ..HashQueueOptions::default()
+        };
+        let mut hash_queue: HashQueue<String> = test_setup_with(options);
+        let item = "queued ".repeat(1000);
+        hash_queue.push_back(item.clone()).unwrap();
+
+        let stored = hash_queue.tree.first().unwrap().unwrap().1;
+        let compressed = Deflate.encode(&Codec::Json.encode(&item).unwrap()).unwrap();
+        assert!(stored.len() < item.len() / 10, "stored {} bytes for a {} byte item", stored.len(), item.len());
+        assert_ne!(compressed, stored.to_vec());
+        assert_eq!(compressed, Xor(0x5a).decode(&stored).unwrap());
+        assert_eq!(Some(item), hash_queue.pop_front().unwrap());
+    }
+
+    #[test]
+    fn should_restore_a_corrupted_set_with_rebuild_set(){
+        let mut hash_queue = test_setup(1u64);
+        hash_queue.extend_back(vec![1, 2, 3]).unwrap();
+        hash_queue.set.remove(&2);
+        hash_queue.set.remove(&3);
+        hash_queue.set.insert(9);
+        let result = hash_queue.pop_back();
+        assert!(matches!(result, Err(HashQueueError::SyncError { .. })), "{:?}", result);
+
+        hash_queue.rebuild_set().unwrap();
+
+        assert!(hash_queue.contains(&2));
+        assert!(!hash_queue.contains(&9));
+        assert_eq!(2, hash_queue.len());
+        assert!(hash_queue.health_check().unwrap().consistent);
+        assert!(!hash_queue.health_check().unwrap().poisoned);
+    }
+
+    #[test]
+    fn should_fail_to_rebuild_set_when_the_tree_holds_duplicates(){
+        let mut hash_queue = test_setup(1u64);
         hash_queue.push_back(1).unwrap();
-        let result = hash_queue.front().unwrap();
-        assert_eq!(Some(1), result);
-        let result = hash_queue.pop_front().unwrap();
-        assert_eq!(Some(1), result);
+        hash_queue.tree.insert(key_from_index(1), bincode::serialize(&1u64).unwrap()).unwrap();
+
+        let result = hash_queue.rebuild_set();
+
+        assert!(matches!(result, Err(HashQueueError::SyncError { .. })), "{:?}", result);
     }
 
+    #[test]
+    fn should_push_and_pop_with_a_custom_config(){
+        let dir = tempfile::tempdir().unwrap();
+        let config = sled::Config::new()
+            .path(dir.path())
+            .cache_capacity(64 * 1024)
+            .flush_every_ms(None);
+        let mut hash_queue: HashQueue<u64> = HashQueue::open_with_config(config, "test").unwrap();
+        hash_queue.push_back(1).unwrap();
+        hash_queue.push_back(2).unwrap();
+
+        assert_eq!(Some(1), hash_queue.pop_front().unwrap());
+        assert_eq!(Some(2), hash_queue.pop_front().unwrap());
+        hash_queue.close().unwrap();
+
+        let temporary = sled::Config::new().temporary(true);
+        let mut hash_queue: HashQueue<u64> = HashQueue::open_with_config(temporary, "test").unwrap();
+        hash_queue.push_back(3).unwrap();
+        assert_eq!(Some(3), hash_queue.pop_front().unwrap());
+        hash_queue.close().unwrap();
+    }
 
     #[test]
-    fn should_fail_to_add_duplicate_item(){
-        let mut hash_queue = test_setup(1u64, "./tests/should_fail_to_add_duplicate_item");
+    fn should_preserve_the_sled_error(){
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("not_a_database");
+        std::fs::write(&path, b"not a database").unwrap();
+
+        let result = HashQueue::<u64>::open(&path, "test");
+
+        assert!(matches!(result, Err(HashQueueError::SledError { error: sled::Error::Io(_) })), "{:?}", result.as_ref().err());
+    }
+
+    #[test]
+    fn should_report_the_size_of_the_database_on_disk(){
+        let hash_queue = test_setup(String::new());
+        let empty = hash_queue.size_on_disk().unwrap();
+        let mut other: HashQueue<String> = HashQueue::from_db(hash_queue.db.as_ref().unwrap(), "other").unwrap();
+
+        other.push_back("a".repeat(100_000)).unwrap();
+
+        let size = hash_queue.size_on_disk().unwrap();
+        assert!(size >= empty + 100_000, "{} bytes on disk, {} when empty", size, empty);
+        assert_eq!(hash_queue.size_on_disk().unwrap(), other.size_on_disk().unwrap());
+        let tree = hash_queue.db.as_ref().unwrap().open_tree("adopted").unwrap();
+        let adopted: HashQueue<String> = HashQueue::from_tree(tree).unwrap();
+        let result = adopted.size_on_disk();
+        assert!(matches!(result, Err(HashQueueError::Unsupported { .. })), "{:?}", result);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn should_push_and_pop_through_the_async_api(){
+        let hash_queue = crate::async_queue::AsyncHashQueue::from(test_setup(1u64));
+        let other = hash_queue.clone();
+        tokio::spawn(async move {
+            other.push_back(1).await.unwrap();
+            other.push_back(2).await.unwrap();
+        }).await.unwrap();
+
+        assert!(!hash_queue.push_back(2).await.unwrap());
+        assert_eq!(2, hash_queue.len().await.unwrap());
+        assert_eq!(Some(1), hash_queue.pop_front().await.unwrap());
+        assert_eq!(Some(2), hash_queue.pop_back().await.unwrap());
+        assert!(hash_queue.is_empty().await.unwrap());
+    }
+
+    #[test]
+    fn should_pop_in_priority_order(){
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path();
+        let mut hash_queue: HashQueue<u64> = HashQueue::open_priority(path, "test").unwrap();
+        for (value, priority) in [(1, 30), (2, -5), (3, 10), (4, 10), (5, 0)] {
+            assert!(hash_queue.push_with_priority(value, priority).unwrap());
+        }
+        assert!(!hash_queue.push_with_priority(3, -100).unwrap());
+        let result = hash_queue.push_back(6);
+        assert!(matches!(result, Err(HashQueueError::Unsupported { .. })), "{:?}", result);
+        assert_eq!(Some(1), hash_queue.pop_back().unwrap());
+        hash_queue.close().unwrap();
+
+        let mut hash_queue: HashQueue<u64> = HashQueue::open_priority(path, "test").unwrap();
+        let result = (0..4).map(|_| hash_queue.pop_front().unwrap().unwrap()).collect::<Vec<u64>>();
+
+        assert_eq!(vec![2, 5, 3, 4], result);
+        assert!(hash_queue.is_empty());
+    }
+
+    #[test]
+    fn should_pop_equal_priorities_in_insertion_order(){
+        let dir = tempfile::tempdir().unwrap();
+        let mut hash_queue: HashQueue<u64> = HashQueue::open_priority(dir.path(), "test").unwrap();
+        for value in [1, 2, 3] {
+            hash_queue.push_with_priority(value, 5).unwrap();
+        }
+        hash_queue.push_with_priority(4, 1).unwrap();
+
+        let result = (0..4).map(|_| hash_queue.pop_front().unwrap().unwrap()).collect::<Vec<u64>>();
+
+        assert_eq!(vec![4, 1, 2, 3], result);
+        assert!(hash_queue.is_empty());
+    }
+
+    #[test]
+    fn should_reject_push_with_priority_on_a_fifo_queue(){
+        let mut hash_queue = test_setup(1u64);
+
+        let result = hash_queue.push_with_priority(1, 0);
+
+        assert!(matches!(result, Err(HashQueueError::Unsupported { .. })), "{:?}", result);
+        assert!(hash_queue.is_empty());
+    }
+
+    #[test]
+    fn should_skip_and_remove_expired_items(){
+        let mut hash_queue = test_setup(1u64);
+        hash_queue.push_back_with_ttl(1, Duration::from_millis(50)).unwrap();
+        hash_queue.push_back_with_ttl(2, Duration::from_secs(60)).unwrap();
+        assert_eq!(Some(1), hash_queue.front().unwrap());
+        std::thread::sleep(Duration::from_millis(100));
+
+        assert_eq!(Some(2), hash_queue.front().unwrap());
+        assert_eq!(2, hash_queue.len());
+        assert_eq!(Some(2), hash_queue.pop_front().unwrap());
+        assert!(hash_queue.is_empty());
+        assert!(!hash_queue.contains(&1));
+    }
+
+    #[test]
+    fn should_purge_expired_items(){
+        let mut hash_queue = test_setup(1u64);
         hash_queue.push_back(1).unwrap();
-        let result = hash_queue.push_back(1).unwrap();
-        assert_eq!(false, result);
+        hash_queue.push_back_with_ttl(2, Duration::from_millis(50)).unwrap();
+        hash_queue.push_back_with_ttl(3, Duration::from_secs(60)).unwrap();
+        hash_queue.push_back_with_ttl(4, Duration::from_millis(50)).unwrap();
+        hash_queue.rotate_n(2).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+
+        let removed = hash_queue.purge_expired().unwrap();
+
+        assert_eq!(2, removed);
+        assert_eq!(0, hash_queue.purge_expired().unwrap());
+        let result = hash_queue.into_iter().collect::<Result<Vec<u64>, _>>().unwrap();
+        assert_eq!(vec![3, 1], result);
     }
 
     #[test]
-    fn should_empty_hash_queue(){
-        let mut hash_queue = test_setup(1u64, "./tests/should_empty_hash_queue");
+    fn should_report_whether_get_or_push_inserted(){
+        let mut hash_queue = test_setup(1u64);
+
+        assert_eq!(PushOutcome::Inserted, hash_queue.get_or_push(1).unwrap());
+        assert_eq!(PushOutcome::Inserted, hash_queue.get_or_push(2).unwrap());
+        assert_eq!(PushOutcome::AlreadyPresent, hash_queue.get_or_push(1).unwrap());
+        assert_eq!(2, hash_queue.len());
+    }
+
+    #[test]
+    fn should_open_independent_queues_from_one_db(){
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let mut a: HashQueue<u64> = HashQueue::from_db(&db, "a").unwrap();
+        let mut b: HashQueue<u64> = HashQueue::from_db(&db, "b").unwrap();
+
+        a.push_back(1).unwrap();
+        b.push_back(1).unwrap();
+        b.push_back(2).unwrap();
+        b.push_back_with_ttl(3, Duration::from_secs(60)).unwrap();
+
+        assert_eq!(1, a.len());
+        assert_eq!(3, b.len());
+        assert_eq!(Some(1), a.pop_front().unwrap());
+        assert!(a.is_empty());
+        assert_eq!(Some(1), b.pop_front().unwrap());
+        assert_eq!(2, b.len());
+    }
+
+    #[test]
+    fn should_read_from_reader_clones_concurrently(){
+        let mut hash_queue = test_setup(1u64);
+        hash_queue.extend_back(vec![1, 2, 3]).unwrap();
+        let reader = hash_queue.reader();
+
+        let handles = (0..4).map(|_| {
+            let reader = reader.clone();
+            std::thread::spawn(move || {
+                for _ in 0..100 {
+                    assert_eq!(Some(1), reader.front().unwrap());
+                    assert_eq!(Some(3), reader.back().unwrap());
+                    assert_eq!(3, reader.len());
+                    assert!(reader.contains(&2).unwrap());
+                    assert!(!reader.contains(&4).unwrap());
+                    assert_eq!(vec![1, 2, 3], reader.iter().collect::<Result<Vec<u64>, _>>().unwrap());
+                }
+            })
+        }).collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        hash_queue.pop_front().unwrap();
+        assert_eq!(Some(2), reader.front().unwrap());
+    }
+
+    #[test]
+    fn should_iterate_from_back_to_front(){
+        let mut hash_queue = test_setup(1u64);
+        hash_queue.extend_back(vec![1, 2, 3]).unwrap();
+
+        let result = hash_queue.iter_rev().collect::<Result<Vec<u64>, _>>().unwrap();
+
+        assert_eq!(vec![3, 2, 1], result);
+        assert_eq!(3, hash_queue.len());
+    }
+
+    #[test]
+    fn should_leave_the_set_unchanged_when_the_tree_insert_fails(){
+        let mut hash_queue = test_setup(1u64);
         hash_queue.push_back(1).unwrap();
-        let result = hash_queue.front().unwrap();
-        assert_eq!(Some(1), result);
-        let result = hash_queue.pop_front().unwrap();
-        assert_eq!(Some(1), result);
-        assert_eq!(true, hash_queue.is_empty());
+        hash_queue.fail_next_insert();
+
+        let result = hash_queue.push_back(2);
+
+        assert!(matches!(result, Err(HashQueueError::SledError { .. })), "{:?}", result);
+        assert!(!hash_queue.contains(&2));
+        assert_eq!(1, hash_queue.len());
+        assert_eq!(1, hash_queue.tree.len());
+        assert!(hash_queue.health_check().unwrap().consistent);
+        assert!(hash_queue.push_back(2).unwrap());
     }
 
     #[test]
-    fn should_produce_items_in_correct_order(){
-        let mut hash_queue= test_setup(1u64, "./tests/should_produce_items_in_correct_order");
+    fn should_check_membership_of_many_items(){
+        let mut hash_queue = test_setup(1u64);
+        hash_queue.extend_back(vec![1, 2, 3]).unwrap();
+
+        assert!(hash_queue.contains_all(&[1, 2]));
+        assert!(!hash_queue.contains_all(&[1, 4]));
+        assert!(hash_queue.contains_any(&[4, 2]));
+        assert!(!hash_queue.contains_any(&[4, 5]));
+        assert!(hash_queue.contains_all(&[]));
+        assert!(!hash_queue.contains_any(&[]));
+    }
 
+    #[test]
+    fn should_fail_to_open_a_tree_holding_duplicates_when_validating(){
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path();
+        let mut hash_queue = test_setup_at(1u64, path);
         hash_queue.push_back(1).unwrap();
         hash_queue.push_back(2).unwrap();
-        hash_queue.push_back(3).unwrap();
+        hash_queue.tree.insert(key_from_index(2), bincode::serialize(&1u64).unwrap()).unwrap();
+        hash_queue.close().unwrap();
 
-        let one = hash_queue.pop_front().unwrap();
-        let two = hash_queue.pop_front().unwrap();
-        let three = hash_queue.pop_front().unwrap();
+        let options = HashQueueOptions { validate_unique: true, ..HashQueueOptions::default() };
+        let result = HashQueue::<u64>::open_with_options(path, "test", options);
 
-        assert_eq!(one, Some(1));
-        assert_eq!(two, Some(2));
-        assert_eq!(three, Some(3));
+        assert!(matches!(result, Err(HashQueueError::SyncError { .. })), "{:?}", result.as_ref().err());
+        let hash_queue: HashQueue<u64> = HashQueue::open(path, "test").unwrap();
+        assert_eq!(3, hash_queue.len());
     }
 
     #[test]
-    fn should_produce_items_in_correct_order_reversed(){
-        let mut hash_queue = test_setup(1u64, "./tests/should_produce_items_in_correct_order_reversed");
+    fn should_pop_and_peek_the_next_front(){
+        let mut hash_queue = test_setup(1u64);
+        hash_queue.extend_back(vec![1, 2, 3]).unwrap();
 
-        hash_queue.push_back(1).unwrap();
+        let result = hash_queue.pop_front_peek_next().unwrap();
+
+        assert_eq!((Some(1), Some(2)), result);
+        assert_eq!(vec![2, 3], hash_queue.iter().collect::<Result<Vec<u64>, _>>().unwrap());
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    struct Versioned {
+        id: u64,
+        payload: String,
+    }
+
+    impl PartialEq for Versioned {
+        fn eq(&self, other: &Self) -> bool {
+            self.id == other.id
+        }
+    }
+
+    impl Eq for Versioned {}
+
+    impl Hash for Versioned {
+        fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+            self.id.hash(state);
+        }
+    }
+
+    #[test]
+    fn should_update_the_payload_in_place(){
+        let versioned = |id: u64, payload: &str| Versioned { id, payload: payload.to_string() };
+        let mut hash_queue = test_setup(versioned(0, ""));
+        hash_queue.extend_back(vec![versioned(1, "old"), versioned(2, "old"), versioned(3, "old")]).unwrap();
+
+        let updated = hash_queue.push_or_update(versioned(2, "new")).unwrap();
+
+        assert!(updated);
+        let result = hash_queue.iter().collect::<Result<Vec<Versioned>, _>>().unwrap();
+        assert_eq!(vec![1, 2, 3], result.iter().map(|item| item.id).collect::<Vec<u64>>());
+        assert_eq!("new", result[1].payload);
+        assert_eq!("old", result[0].payload);
+        assert_eq!("new", hash_queue.set.get(&versioned(2, "")).unwrap().payload);
+        assert!(!hash_queue.push_or_update(versioned(4, "new")).unwrap());
+        assert_eq!(4, hash_queue.back().unwrap().unwrap().id);
+    }
+
+    #[test]
+    fn should_refuse_a_database_with_data_in_the_default_tree(){
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path();
+        {
+            let db = sled::open(path).unwrap();
+            db.insert("stray", "value").unwrap();
+            db.flush().unwrap();
+        }
+        wait_until_closed(path);
+
+        let result: Result<HashQueue<i32>, _> = HashQueue::open(path, "test");
+
+        assert!(matches!(&result, Err(HashQueueError::UnexpectedDefaultTreeData { name }) if name == "test"), "{:?}", result.as_ref().err());
+        wait_until_closed(path);
+        {
+            let db = sled::open(path).unwrap();
+            db.clear().unwrap();
+            db.flush().unwrap();
+        }
+        wait_until_closed(path);
+        let hash_queue: Result<HashQueue<i32>, _> = HashQueue::open(path, "test");
+        assert!(hash_queue.is_ok());
+    }
+
+    #[test]
+    fn should_refuse_a_shared_database_with_data_in_the_default_tree(){
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        db.insert("stray", "value").unwrap();
+
+        let result: Result<HashQueue<i32>, _> = HashQueue::from_db(&db, "test");
+
+        assert!(matches!(&result, Err(HashQueueError::UnexpectedDefaultTreeData { name }) if name == "test"), "{:?}", result.as_ref().err());
+        db.clear().unwrap();
+        assert!(HashQueue::<i32>::from_db(&db, "test").is_ok());
+    }
+
+    #[test]
+    fn should_truncate_the_newest_elements(){
+        let mut hash_queue = test_setup(1);
+        hash_queue.extend_back(vec![1, 2, 3, 4]).unwrap();
+
+        let removed = hash_queue.truncate_back(2).unwrap();
+
+        assert_eq!(2, removed);
+        assert_eq!(vec![1, 2], hash_queue.iter().collect::<Result<Vec<i32>, _>>().unwrap());
+        assert!(!hash_queue.contains(&3));
+        assert!(!hash_queue.contains(&4));
+        assert_eq!(2, hash_queue.truncate_back(5).unwrap());
+        assert!(hash_queue.is_empty());
+    }
+
+    #[test]
+    fn should_leave_corrupt_back_in_place_when_truncating(){
+        let mut hash_queue = test_setup(1u64);
+        hash_queue.extend_back(vec![1, 2]).unwrap();
+        hash_queue.tree.insert(key_from_index(2), vec![1u8, 2, 3]).unwrap();
+
+        let result = hash_queue.truncate_back(2);
+        assert!(matches!(result, Err(HashQueueError::BinCodeError { .. })), "{:?}", result);
+        assert_eq!(3, hash_queue.tree.len());
+        assert!(hash_queue.contains(&1));
+        assert!(hash_queue.contains(&2));
+    }
+
+    #[test]
+    fn should_return_an_error_when_the_flush_after_a_push_fails(){
+        let mut hash_queue = test_setup(1u64);
+        hash_queue.fail_next_flush();
+
+        let result = hash_queue.push_back(1);
+
+        assert!(matches!(result, Err(HashQueueError::SledError { error: sled::Error::Io(_) })), "{:?}", result);
+        assert!(hash_queue.contains(&1));
+        assert_eq!(1, hash_queue.tree.len());
+        assert!(hash_queue.push_back(2).unwrap());
+    }
+
+    #[test]
+    fn should_keep_working_after_compacting_a_churned_queue(){
+        let mut hash_queue = test_setup(1u64);
+        hash_queue.set_autoflush(false);
+        let mut sizes = Vec::new();
+        for round in 0..25u64 {
+            hash_queue.extend_back((round * 2000..(round + 1) * 2000).collect::<Vec<u64>>()).unwrap();
+            hash_queue.drop_front(1990).unwrap();
+            hash_queue.compact().unwrap();
+            sizes.push(hash_queue.size_on_disk().unwrap());
+        }
+
+        //Each round writes a sizeable fraction of what the database holds after the first few, so if removed elements' space wasn't reused it would keep growing.
+        let settled = sizes[9];
+        assert!(sizes[10..].iter().all(|&size| size <= 2 * settled), "{:?}", sizes);
+        assert_eq!(250, hash_queue.len());
+        assert!(hash_queue.push_back(10_000).unwrap());
+        assert_eq!(Some(49_750), hash_queue.pop_front().unwrap());
+        assert!(hash_queue.health_check().unwrap().consistent);
+    }
+
+    #[test]
+    fn should_find_the_position_of_an_item(){
+        let mut hash_queue = test_setup(1);
+        hash_queue.extend_back(vec![10, 20, 30]).unwrap();
+
+        assert_eq!(Some(0), hash_queue.position(&10).unwrap());
+        assert_eq!(Some(1), hash_queue.position(&20).unwrap());
+        assert_eq!(None, hash_queue.position(&99).unwrap());
+    }
+
+    #[test]
+    fn should_wrap_a_populated_tree(){
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = db.open_tree("wrapped").unwrap();
+        for (index, value) in [4u64, 5, 6].into_iter().enumerate() {
+            tree.insert(key_from_index(index as i64), bincode::serialize(&value).unwrap()).unwrap();
+        }
+
+        let hash_queue: HashQueue<u64> = HashQueue::from_tree(tree.clone()).unwrap();
+
+        assert_eq!(3, hash_queue.len());
+        assert_eq!(Some(4), hash_queue.front().unwrap());
+        assert!(hash_queue.contains(&5));
+        assert!(!hash_queue.contains(&7));
+
+        tree.insert(key_from_index(3), bincode::serialize(&5u64).unwrap()).unwrap();
+        let result = HashQueue::<u64>::from_tree(tree);
+        assert!(matches!(result, Err(HashQueueError::SyncError { .. })), "{:?}", result.as_ref().err());
+    }
+
+    #[test]
+    fn should_pop_a_batch_from_the_front(){
+        let mut hash_queue = test_setup(1);
+        hash_queue.extend_back(vec![1, 2, 3]).unwrap();
+
+        assert_eq!(vec![1, 2], hash_queue.pop_front_n(2).unwrap());
+        assert!(!hash_queue.contains(&1));
+        assert!(hash_queue.contains(&3));
+        assert_eq!(vec![3], hash_queue.pop_front_n(5).unwrap());
+        assert_eq!(Vec::<i32>::new(), hash_queue.pop_front_n(5).unwrap());
+        assert!(hash_queue.is_empty());
+    }
+
+    #[test]
+    fn should_summarize_the_queue(){
+        let mut hash_queue = test_setup(1);
+
+        let empty = hash_queue.stats().unwrap();
+        assert_eq!(0, empty.len);
+        assert_eq!(None, empty.front);
+        assert_eq!(None, empty.back);
+        assert_eq!("test: empty, 0 rejected duplicates", empty.to_string());
+
+        hash_queue.extend_back(vec![1, 2, 3]).unwrap();
         hash_queue.push_back(2).unwrap();
-        hash_queue.push_back(3).unwrap();
+        let stats = hash_queue.stats().unwrap();
 
-        let one = hash_queue.pop_back().unwrap();
-        let two = hash_queue.pop_back().unwrap();
-        let three = hash_queue.pop_back().unwrap();
+        assert_eq!(3, stats.len);
+        assert_eq!(1, stats.rejected_duplicates);
+        assert_eq!(Some(1), stats.front);
+        assert_eq!(Some(3), stats.back);
+        assert_eq!("test: 3 elements, 1 rejected duplicates, front Some(1), back Some(3)", stats.to_string());
+    }
 
-        assert_eq!(one, Some(3));
-        assert_eq!(two, Some(2));
-        assert_eq!(three, Some(1));
+    #[test]
+    fn should_report_how_long_an_item_waited(){
+        let options = HashQueueOptions {
+            timestamps: true,
+            ..HashQueueOptions::default()
+        };
+        let mut hash_queue: HashQueue<u64> = test_setup_with(options);
+        hash_queue.extend_back(vec![1, 2]).unwrap();
+
+        std::thread::sleep(Duration::from_millis(50));
+        let (item, waited) = hash_queue.pop_front_timed().unwrap().unwrap();
+
+        assert_eq!(1, item);
+        assert!(waited >= Duration::from_millis(50), "{:?}", waited);
+        assert_eq!(Some(2), hash_queue.front().unwrap());
+        assert!(!hash_queue.contains(&1));
+        assert_eq!(Some(2), hash_queue.pop_front().unwrap());
+        assert_eq!(None, hash_queue.pop_front_timed().unwrap());
+    }
+
+    #[test]
+    fn should_refuse_a_timed_pop_without_timestamps(){
+        let mut hash_queue = test_setup(1u64);
+        hash_queue.push_back(1).unwrap();
+
+        let result = hash_queue.pop_front_timed();
+
+        assert!(matches!(result, Err(HashQueueError::Unsupported { .. })), "{:?}", result);
+        assert_eq!(1, hash_queue.len());
+    }
+
+    #[test]
+    fn should_keep_unflushed_pushes_after_drop(){
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path();
+        let mut hash_queue = test_setup_at(1u64, path);
+        hash_queue.set_autoflush(false);
+        hash_queue.push_back(1).unwrap();
+
+        drop(hash_queue);
+        wait_until_closed(path);
+
+        let hash_queue: HashQueue<u64> = HashQueue::open(path, "test").unwrap();
+        assert_eq!(Some(1), hash_queue.front().unwrap());
+        assert!(hash_queue.contains(&1));
+    }
+
+    #[test]
+    fn should_append_another_queue(){
+        let mut hash_queue = test_setup(1);
+        let mut other = test_setup(1);
+        hash_queue.extend_back(vec![2, 3]).unwrap();
+        other.extend_back(vec![1, 2]).unwrap();
+
+        let added = hash_queue.append(&mut other).unwrap();
+
+        assert_eq!(1, added);
+        assert_eq!(vec![2, 3, 1], hash_queue.iter().collect::<Result<Vec<i32>, _>>().unwrap());
+        assert!(other.is_empty());
+        assert!(other.tree.is_empty());
+        assert_eq!(3, hash_queue.len());
+        assert!(hash_queue.contains(&1));
+        assert!(hash_queue.autoflush);
+        assert!(other.autoflush);
+    }
+
+    fn namespaced_setup() -> NamespacedHashQueue<u64> {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        NamespacedHashQueue::from_tree(db.open_tree("tenants").unwrap()).unwrap()
+    }
+
+    #[test]
+    fn should_keep_namespaces_independent(){
+        let mut hash_queue = namespaced_setup();
+        for i in 1..=3u64 {
+            hash_queue.push_back("alice", i).unwrap();
+            hash_queue.push_back("bob", 10 - i).unwrap();
+        }
+
+        assert!(!hash_queue.push_back("alice", 2).unwrap());
+        assert!(hash_queue.push_back("bob", 2).unwrap());
+        assert_eq!(3, hash_queue.len("alice"));
+        assert_eq!(4, hash_queue.len("bob"));
+        assert_eq!(Some(9), hash_queue.front("bob").unwrap());
+        assert_eq!(Some(2), hash_queue.back("bob").unwrap());
+        assert_eq!(Some(1), hash_queue.pop_front("alice").unwrap());
+        assert_eq!(Some(9), hash_queue.pop_front("bob").unwrap());
+        assert_eq!(Some(2), hash_queue.pop_front("alice").unwrap());
+        assert_eq!(Some(3), hash_queue.pop_front("alice").unwrap());
+        assert_eq!(None, hash_queue.pop_front("alice").unwrap());
+        assert!(hash_queue.is_empty("alice"));
+        assert_eq!(vec![b"bob"], hash_queue.namespaces().collect::<Vec<&[u8]>>());
+        assert_eq!(Some(8), hash_queue.pop_front("bob").unwrap());
+    }
+
+    #[test]
+    fn should_not_mix_namespaces_that_share_a_prefix(){
+        let mut hash_queue = namespaced_setup();
+        hash_queue.push_back("ab", 1).unwrap();
+        hash_queue.push_back("abc", 2).unwrap();
+        hash_queue.push_back("a", 3).unwrap();
+
+        assert_eq!(Some(1), hash_queue.front("ab").unwrap());
+        assert_eq!(Some(1), hash_queue.back("ab").unwrap());
+        assert_eq!(Some(3), hash_queue.pop_front("a").unwrap());
+        assert_eq!(None, hash_queue.pop_front("a").unwrap());
+        assert_eq!(1, hash_queue.len("abc"));
+    }
+
+    #[test]
+    fn should_rebuild_namespaces_from_the_tree(){
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = db.open_tree("tenants").unwrap();
+        let mut hash_queue: NamespacedHashQueue<u64> = NamespacedHashQueue::from_tree(tree.clone()).unwrap();
+        hash_queue.push_back("alice", 1).unwrap();
+        hash_queue.push_back("alice", 2).unwrap();
+        hash_queue.push_back("bob", 1).unwrap();
+
+        let mut reopened: NamespacedHashQueue<u64> = NamespacedHashQueue::from_tree(tree.clone()).unwrap();
+
+        assert_eq!(2, reopened.len("alice"));
+        assert!(reopened.contains("bob", &1));
+        assert_eq!(Some(1), reopened.pop_front("alice").unwrap());
+        tree.insert(b"not a namespaced key", bincode::serialize(&1u64).unwrap()).unwrap();
+        let result = NamespacedHashQueue::<u64>::from_tree(tree);
+        assert!(matches!(result, Err(HashQueueError::InvalidKey { .. })), "{:?}", result.as_ref().err());
+    }
+
+    #[test]
+    fn should_return_the_stored_bytes_of_each_end(){
+        let mut hash_queue = test_setup("".to_string());
+        assert_eq!(None, hash_queue.front_raw().unwrap());
+        assert_eq!(None, hash_queue.back_raw().unwrap());
+        hash_queue.extend_back(vec!["a".to_string(), "b".to_string()]).unwrap();
+
+        let front = hash_queue.front_raw().unwrap().unwrap();
+        let back = hash_queue.back_raw().unwrap().unwrap();
+
+        assert_eq!(front.as_ref(), bincode::serialize(&hash_queue.front().unwrap().unwrap()).unwrap().as_slice());
+        assert_eq!(back.as_ref(), bincode::serialize(&hash_queue.back().unwrap().unwrap()).unwrap().as_slice());
+        hash_queue.tree.insert(key_from_index(2), vec![1u8]).unwrap();
+        assert_eq!([1u8], hash_queue.back_raw().unwrap().unwrap().as_ref());
+        assert!(hash_queue.back().is_err());
     }
 
 }
\ No newline at end of file
@@ -1,2 +1,8 @@
 pub mod hash_queue;
-pub mod errors;
\ No newline at end of file
+pub mod errors;
+pub mod options;
+pub mod telemetry;
+pub mod codec;
+pub mod namespaced;
+#[cfg(feature = "tokio")]
+pub mod async_queue;
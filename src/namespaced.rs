@@ -0,0 +1,211 @@
+//! Many small queues stored in a single sled tree, one per namespace, for applications with more tenants than it is reasonable to open a tree for.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sled::{IVec, Tree};
+
+use crate::errors::HashQueueError;
+use crate::hash_queue::{index_from_key, key_from_index};
+use crate::options::HashQueueOptions;
+
+///Name: NamespacedHashQueue
+///
+/// Desc: A set of independent HashQueues, one per namespace, all stored in a single sled tree. Each namespace is its own FIFO queue, and only rejects
+/// values that are already queued in that namespace, so the same value can be queued once in every namespace. This is intended for multi-tenant
+/// applications that keep a queue per tenant, where there are too many tenants to open a tree for each.
+///
+/// Additional notes: Each element is stored under the length of its namespace as 2 big endian bytes, then the namespace, then its index in the namespace, the same
+///                   way `HashQueue` stores it. A namespace can be at most `u16::MAX` bytes long. The set of every namespace is built when the queue is opened, so opening
+///                   reads the whole tree, like `HashQueue::open` does. Namespaces with no elements aren't stored, and take up no memory.
+///
+/// Usage:
+///```
+/// use set_deque::namespaced::NamespacedHashQueue;
+///
+/// let db = sled::Config::new().temporary(true).open().unwrap();
+/// let mut hash_queue = NamespacedHashQueue::from_tree(db.open_tree("tenants").unwrap()).unwrap();
+///
+/// hash_queue.push_back("alice", 1).unwrap();
+/// hash_queue.push_back("bob", 1).unwrap();
+///
+/// assert_eq!(Some(1), hash_queue.pop_front("alice").unwrap());
+/// assert_eq!(1, hash_queue.len("bob"));
+/// ```
+pub struct NamespacedHashQueue<T> {
+    tree: Tree,
+    sets: HashMap<Vec<u8>, HashSet<T>>,
+    options: HashQueueOptions,
+}
+
+impl<T> NamespacedHashQueue<T>
+    where
+        T: Hash + Eq + Clone + Serialize + DeserializeOwned + Debug,
+{
+    ///Name: open
+    ///
+    /// Desc: This function opens the tree named `name` in the database at `path`, and builds the set of every namespace stored in it.
+    pub fn open<P: AsRef<Path>, V: AsRef<[u8]>>(path: P, name: V) -> Result<Self, HashQueueError> {
+        let db = sled::open(path)?;
+        Self::from_tree(db.open_tree(name)?)
+    }
+
+    ///Name: from_tree
+    ///
+    /// Desc: This function adopts an already open sled tree, and builds the set of every namespace stored in it. If a key in the tree isn't a namespaced index,
+    /// this returns `HashQueueError::InvalidKey`, and if a namespace stores a value more than once, this returns `HashQueueError::SyncError`.
+    pub fn from_tree(tree: Tree) -> Result<Self, HashQueueError> {
+        let options = HashQueueOptions::default();
+        let mut sets: HashMap<Vec<u8>, HashSet<T>> = HashMap::new();
+        for entry in tree.iter() {
+            let (key, val) = entry?;
+            let Some((namespace, _index)) = split_key(&key) else {
+                return Err(HashQueueError::InvalidKey { key: key.to_vec() });
+            };
+            let item: T = options.decode(&val)?;
+            if !sets.entry(namespace.to_vec()).or_default().insert(item) {
+                return Err(HashQueueError::SyncError {
+                    message: format!("from_tree: namespace {:?} stores a value more than once", String::from_utf8_lossy(namespace)),
+                });
+            }
+        }
+        Ok(Self { tree, sets, options })
+    }
+
+    ///Name: push_back
+    ///
+    /// Desc: This function pushes `value` onto the back of the queue for `namespace`. Like `HashQueue::push_back`, this returns ```Ok(true)``` if the value was added,
+    /// and ```Ok(false)``` if it was already queued in that namespace. Other namespaces aren't checked.
+    pub fn push_back<N: AsRef<[u8]>>(&mut self, namespace: N, value: T) -> Result<bool, HashQueueError> {
+        let namespace = namespace.as_ref();
+        let prefix = prefix(namespace)?;
+        if self.contains(namespace, &value) {
+            return Ok(false);
+        }
+        let val = self.options.encode(&value)?;
+        //Another handle on the tree may push to the same index first, in which case the back is read again, like HashQueue's insert_free.
+        loop {
+            let index = match self.tree.scan_prefix(&prefix).keys().next_back() {
+                Some(key) => index_from_key(&key?[prefix.len()..]).checked_add(1).ok_or(HashQueueError::IndexOverflow { index: i64::MAX })?,
+                None => 0,
+            };
+            let key = [&prefix[..], &key_from_index(index)].concat();
+            if self.tree.compare_and_swap(key, None as Option<&[u8]>, Some(val.as_slice()))?.is_ok() {
+                break;
+            }
+        }
+        self.sets.entry(namespace.to_vec()).or_default().insert(value);
+        self.tree.flush()?;
+        Ok(true)
+    }
+
+    ///Name: pop_front
+    ///
+    /// Desc: This function removes and returns the front of the queue for `namespace`, if it has one. Other namespaces aren't modified.
+    pub fn pop_front<N: AsRef<[u8]>>(&mut self, namespace: N) -> Result<Option<T>, HashQueueError> {
+        let namespace = namespace.as_ref();
+        let prefix = prefix(namespace)?;
+        let data = loop {
+            let Some((key, val)) = self.tree.scan_prefix(&prefix).next().transpose()? else {
+                return Ok(None);
+            };
+            let data: T = self.options.decode(&val)?;
+            if self.tree.compare_and_swap(&key, Some(&val), None as Option<&[u8]>)?.is_ok() {
+                break data;
+            }
+        };
+        if !self.release(namespace, &data) {
+            return Err(HashQueueError::SyncError {
+                message: "pop_front".to_string(),
+            });
+        }
+        self.tree.flush()?;
+        Ok(Some(data))
+    }
+
+    ///Name: front
+    ///
+    /// Desc: This function returns the front of the queue for `namespace`, if it has one, without removing it.
+    pub fn front<N: AsRef<[u8]>>(&self, namespace: N) -> Result<Option<T>, HashQueueError> {
+        let prefix = prefix(namespace.as_ref())?;
+        self.decode_entry(self.tree.scan_prefix(prefix).next())
+    }
+
+    ///Name: back
+    ///
+    /// Desc: This function returns the back of the queue for `namespace`, if it has one, without removing it.
+    pub fn back<N: AsRef<[u8]>>(&self, namespace: N) -> Result<Option<T>, HashQueueError> {
+        let prefix = prefix(namespace.as_ref())?;
+        self.decode_entry(self.tree.scan_prefix(prefix).next_back())
+    }
+
+    ///Name: len
+    ///
+    /// Desc: This function returns how many elements are queued in `namespace`, using the size of its set, so it is O(1).
+    pub fn len<N: AsRef<[u8]>>(&self, namespace: N) -> usize {
+        self.sets.get(namespace.as_ref()).map_or(0, HashSet::len)
+    }
+
+    ///Name: is_empty
+    ///
+    /// Desc: This function returns whether nothing is queued in `namespace`.
+    pub fn is_empty<N: AsRef<[u8]>>(&self, namespace: N) -> bool {
+        self.len(namespace) == 0
+    }
+
+    ///Name: contains
+    ///
+    /// Desc: This function returns whether `value` is queued in `namespace`. It checks the namespace's set rather than reading the tree, so it is O(1).
+    pub fn contains<N: AsRef<[u8]>>(&self, namespace: N, value: &T) -> bool {
+        self.sets.get(namespace.as_ref()).is_some_and(|set| set.contains(value))
+    }
+
+    ///Name: namespaces
+    ///
+    /// Desc: This function returns every namespace that has at least one element queued, in no particular order.
+    pub fn namespaces(&self) -> impl Iterator<Item = &[u8]> + '_ {
+        self.sets.keys().map(Vec::as_slice)
+    }
+
+    //This is an internal function that removes `value` from the set of `namespace`, and drops the set once the namespace is empty.
+    fn release(&mut self, namespace: &[u8], value: &T) -> bool {
+        let Some(set) = self.sets.get_mut(namespace) else {
+            return false;
+        };
+        let removed = set.remove(value);
+        if set.is_empty() {
+            self.sets.remove(namespace);
+        }
+        removed
+    }
+
+    //This is an internal function that deserializes the value of an entry read from the tree, if there was one.
+    fn decode_entry(&self, entry: Option<Result<(IVec, IVec), sled::Error>>) -> Result<Option<T>, HashQueueError> {
+        match entry {
+            Some(entry) => Ok(Some(self.options.decode(&entry?.1)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+//Builds the prefix every key in `namespace` starts with: the length of the namespace as 2 big endian bytes, then the namespace.
+fn prefix(namespace: &[u8]) -> Result<Vec<u8>, HashQueueError> {
+    let Ok(len) = u16::try_from(namespace.len()) else {
+        return Err(HashQueueError::Unsupported {
+            message: format!("a namespace can be at most {} bytes long, this one is {} bytes", u16::MAX, namespace.len()),
+        });
+    };
+    Ok([&len.to_be_bytes()[..], namespace].concat())
+}
+
+//Splits a key into its namespace and index, or returns None if it isn't a namespaced index.
+fn split_key(key: &[u8]) -> Option<(&[u8], i64)> {
+    let len = u16::from_be_bytes(key.get(..2)?.try_into().ok()?) as usize;
+    let namespace = key.get(2..2 + len)?;
+    let index = key.get(2 + len..).filter(|index| index.len() == 8)?;
+    Some((namespace, index_from_key(index)))
+}
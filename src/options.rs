@@ -0,0 +1,147 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::codec::{ByteCodec, Codec};
+use crate::errors::HashQueueError;
+use crate::hash_queue::unix_millis;
+
+/// Options used to change the behavior of a HashQueue when it is opened via `HashQueue::open_with_options`.
+/// The default options give the same behavior as `HashQueue::open`.
+#[derive(Debug, Clone, Default)]
+pub struct HashQueueOptions {
+    /// When set, pushes are buffered and flushed to disk together rather than flushing after every push. See `WriteBuffer`.
+    pub write_buffer: Option<WriteBuffer>,
+    /// Which pushes are rejected as duplicates. Defaults to `DedupMode::Global`.
+    pub dedup: DedupMode,
+    /// Keep a snapshot of the set in a sidecar tree, so that `open` can load the set directly rather than rebuilding it by scanning the whole queue.
+    /// The snapshot is written by `HashQueue::flush` and `HashQueue::close`, and only used if nothing has modified the queue since it was written,
+    /// otherwise `open` falls back to scanning the queue. Off by default.
+    pub snapshot_set: bool,
+    /// The largest an element may be once serialized, in bytes. Pushing a larger element returns `HashQueueError::ElementTooLarge` without writing anything.
+    /// Defaults to `None`, which doesn't limit the size of elements.
+    pub max_element_bytes: Option<usize>,
+    /// The most elements the queue may hold. Pushing onto a full queue returns `HashQueueError::CapacityExceeded` without writing anything.
+    /// Defaults to `None`, which doesn't limit the length of the queue.
+    pub capacity: Option<usize>,
+    /// Read every element of the queue when it is opened, so sled's page cache is warm before the first operation rather than filling up as the queue is used.
+    /// This makes opening a long queue slower, in exchange for avoiding slow reads from disk right after opening. Off by default.
+    pub eager_load: bool,
+    /// What `pop_front` does when the element at the front of the queue can't be deserialized. Defaults to `CorruptPolicy::Fail`.
+    pub corrupt_policy: CorruptPolicy,
+    /// How elements are encoded into the bytes stored in sled. Defaults to `Codec::Bincode`.
+    pub codec: Codec,
+    /// Compress each element with DEFLATE after it has been encoded, and decompress it before it is decoded. This makes large, repetitive elements,
+    /// like JSON blobs, much smaller on disk, at the cost of some CPU on every push and read. The keys aren't compressed, so the order of the queue is unchanged.
+    /// Like the codec, a queue must always be opened with the same setting it was written with. Off by default.
+    #[cfg(feature = "compression")]
+    pub compress: bool,
+    /// A transformation applied to each element's bytes after it has been encoded and compressed, and undone before it is decompressed and decoded,
+    /// for example encryption. Codecs can be chained with `CompositeCodec`, so `CompositeCodec::new(Deflate, cipher)` compresses elements and then encrypts them.
+    /// Like the codec, a queue must always be opened with the same byte codec it was written with. Defaults to `None`, which stores the encoded bytes as they are.
+    pub byte_codec: Option<Arc<dyn ByteCodec>>,
+    /// Store elements by priority rather than in the order they are pushed, so `pop_front` returns the element with the lowest priority.
+    /// Elements are pushed with `HashQueue::push_with_priority`, and pushes that need a position in FIFO order, like `push_back`, return `HashQueueError::Unsupported`.
+    /// A queue must always be opened with the same setting it was written with. Off by default.
+    pub priority: bool,
+    /// Fail to open the queue with `HashQueueError::SyncError` if the tree stores any value more than once, to catch corruption when the queue is loaded rather than at the first pop.
+    /// Off by default, since `HashQueue::push_back_force`, `DedupMode::AdjacentOnly` and `DedupMode::Off` store extra copies on purpose, and a queue holding them would fail to open.
+    pub validate_unique: bool,
+    /// Store the time each element was pushed alongside it, so `HashQueue::pop_front_timed` can report how long the element waited in the queue.
+    /// The time is read from the system clock, in milliseconds, and adds 8 bytes to every stored element. Elements written by `replace` or `push_or_update`
+    /// get the time they were written. A queue must always be opened with the same setting it was written with. Off by default.
+    pub timestamps: bool,
+}
+
+impl HashQueueOptions {
+    //This is an internal function that returns how long every key in the tree is. A priority key is the priority followed by a sequence number, see push_with_priority.
+    pub(crate) fn key_len(&self) -> usize {
+        if self.priority { 16 } else { 8 }
+    }
+
+    //These are internal functions that turn an element into the bytes stored in the tree and back, using the codec, compression and byte codec these options choose.
+    //When timestamps are on, the time the element was pushed is stored in front of it, outside of any compression, so it can be read without decoding the element.
+    pub(crate) fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, HashQueueError> {
+        let bytes = self.codec.encode(value)?;
+        #[cfg(feature = "compression")]
+        let bytes = if self.compress { crate::codec::compress(&bytes)? } else { bytes };
+        let bytes = match &self.byte_codec {
+            Some(byte_codec) => byte_codec.encode(&bytes)?,
+            None => bytes,
+        };
+        if self.timestamps {
+            return Ok([&unix_millis().to_be_bytes()[..], &bytes].concat());
+        }
+        Ok(bytes)
+    }
+
+    pub(crate) fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, HashQueueError> {
+        let bytes = if self.timestamps { &bytes[TIMESTAMP_LEN.min(bytes.len())..] } else { bytes };
+        let decoded;
+        let bytes = match &self.byte_codec {
+            Some(byte_codec) => {
+                decoded = byte_codec.decode(bytes)?;
+                decoded.as_slice()
+            }
+            None => bytes,
+        };
+        #[cfg(feature = "compression")]
+        if self.compress {
+            return self.codec.decode(&crate::codec::decompress(bytes)?);
+        }
+        self.codec.decode(bytes)
+    }
+
+    //This is an internal function that reads the time, in milliseconds since the unix epoch, an element was pushed from the bytes stored for it. It returns None if timestamps are off.
+    pub(crate) fn pushed_at(&self, bytes: &[u8]) -> Option<u64> {
+        let stamp = bytes.get(..TIMESTAMP_LEN).filter(|_| self.timestamps)?;
+        Some(u64::from_be_bytes(stamp.try_into().ok()?))
+    }
+}
+
+//How many bytes the time an element was pushed takes up in front of it, see `HashQueueOptions::timestamps`.
+const TIMESTAMP_LEN: usize = 8;
+
+/// Controls which pushes a HashQueue rejects as duplicates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DedupMode {
+    /// A push is rejected if the value is anywhere in the queue.
+    #[default]
+    Global,
+    /// A push is rejected only if the value equals the current back of the queue, so the same value can appear again later in the queue.
+    AdjacentOnly,
+    /// No push is rejected, so the queue is a plain FIFO that can hold any number of copies of a value. The set still tracks every copy,
+    /// so `contains` and `len` stay O(1).
+    Off,
+}
+
+/// Controls what `HashQueue::pop_front` does when the element at the front of the queue can't be deserialized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CorruptPolicy {
+    /// The pop fails with the error the codec returned, and the element is left at the front of the queue.
+    #[default]
+    Fail,
+    /// The element is deleted, and the pop moves on to the next element.
+    SkipAndDelete,
+    /// The element is moved to a sidecar tree named after the queue with `::quarantine` appended, and the pop moves on to the next element.
+    /// This needs the queue to have opened its own database.
+    SkipAndQuarantine,
+}
+
+/// Name: WriteBuffer
+///
+/// Desc: Controls how many pushes may be buffered before they are flushed to disk as a single batch.
+/// Buffered pushes are written to sled immediately, so every read sees them, but they are only made durable when
+/// the buffer flushes. The buffer flushes once it holds `capacity` pushes, or on the first push after `max_delay` has elapsed
+/// since the oldest buffered push. Calling `HashQueue::flush`, or any operation that flushes the tree (like a pop), also flushes the buffer.
+///
+/// Additional notes: The durability window is the set of buffered pushes. If the process crashes before the buffer flushes, up to `capacity - 1`
+///                   pushes may be lost. Sled also flushes in the background on its own schedule (every 500ms by default), which bounds the window in time
+///                   even when no further pushes arrive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WriteBuffer {
+    pub capacity: usize,
+    pub max_delay: Duration,
+}
@@ -0,0 +1,44 @@
+//! Metrics reported by a HashQueue when the `metrics` feature is enabled. Every metric is labeled with the queue's name
+//! under the `queue` label, and is reported through the `metrics` crate facade, so any installed recorder (e.g. a Prometheus exporter) will pick them up.
+//! Without the feature, every function in this module compiles down to a no-op.
+
+/// Gauge holding the number of elements currently in the queue.
+pub const QUEUE_LENGTH: &str = "hash_queue_length";
+/// Counter of elements successfully pushed onto the queue.
+pub const PUSHES_TOTAL: &str = "hash_queue_pushes_total";
+/// Counter of elements popped off of the queue.
+pub const POPS_TOTAL: &str = "hash_queue_pops_total";
+/// Counter of elements discarded from the queue without being popped, e.g. by `clear`.
+pub const EVICTIONS_TOTAL: &str = "hash_queue_evictions_total";
+
+#[cfg(feature = "metrics")]
+pub(crate) fn record_push(queue: &str, len: usize) {
+    metrics::counter!(PUSHES_TOTAL, "queue" => queue.to_string()).increment(1);
+    record_length(queue, len);
+}
+
+#[cfg(feature = "metrics")]
+pub(crate) fn record_pop(queue: &str, len: usize) {
+    metrics::counter!(POPS_TOTAL, "queue" => queue.to_string()).increment(1);
+    record_length(queue, len);
+}
+
+#[cfg(feature = "metrics")]
+pub(crate) fn record_evictions(queue: &str, evicted: usize, len: usize) {
+    metrics::counter!(EVICTIONS_TOTAL, "queue" => queue.to_string()).increment(evicted as u64);
+    record_length(queue, len);
+}
+
+#[cfg(feature = "metrics")]
+fn record_length(queue: &str, len: usize) {
+    metrics::gauge!(QUEUE_LENGTH, "queue" => queue.to_string()).set(len as f64);
+}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn record_push(_queue: &str, _len: usize) {}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn record_pop(_queue: &str, _len: usize) {}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn record_evictions(_queue: &str, _evicted: usize, _len: usize) {}